@@ -1,15 +1,372 @@
+extern crate getopts;
 extern crate markov;
 
+use getopts::{
+    optflag,
+    optopt,
+    Matches,
+    OptGroup,
+};
 use std::collections::HashMap;
-use markov::MarkovGenerator;
+use std::io;
+use std::io::File;
+use std::os;
+use std::rand::{
+    task_rng,
+    Rng,
+};
+use std::str::FromStr;
+
+use markov::{
+    seeded_rng,
+    tokenizer_by_name,
+    Cache,
+    FeedSummary,
+    MarkovGenerator,
+    RecordFormat,
+    Smoothing,
+    Symbol,
+    Transition,
+};
+
+type Model = MarkovGenerator<HashMap<Vec<Symbol>, Vec<Transition>>>;
+
+static USAGE: &'static str = "\
+Usage: markovgen <command> [options] [arguments]
+
+Commands:
+    train       Train a model on corpus files, directories or glob patterns
+    generate    Generate text from a model
+    score       Score text against a model
+    stats       Show statistics about a model
+    inspect     Show the successors of a state
+
+Run `markovgen <command> --help` for the options of a command.";
+
+/// Why a command failed: usage errors exit with 2, other errors with 1.
+enum Failure {
+    Usage(String),
+    Error(String),
+}
 
 fn main() {
-    let args = ::std::os::args();
-    let path = Path::new(args[1].as_slice());
+    let args = os::args();
+    let result = match args.get(1).map(|command| command.as_slice()) {
+        Some("train") => train(args[2..]),
+        Some("generate") => generate(args[2..]),
+        Some("score") => score(args[2..]),
+        Some("stats") => stats(args[2..]),
+        Some("inspect") => inspect(args[2..]),
+        Some("help") | Some("-h") | Some("--help") => {
+            println!("{}", USAGE);
+            return;
+        }
+        Some(command) => Err(Failure::Usage(format!("unknown command `{}`", command))),
+        None => Err(Failure::Usage("missing command".to_string())),
+    };
+
+    let mut stderr = io::stderr();
+    match result {
+        Ok(()) => {}
+        Err(Failure::Usage(message)) => {
+            let _ = writeln!(&mut stderr, "markovgen: {}\n\n{}", message, USAGE);
+            os::set_exit_status(2);
+        }
+        Err(Failure::Error(message)) => {
+            let _ = writeln!(&mut stderr, "markovgen: {}", message);
+            os::set_exit_status(1);
+        }
+    }
+}
+
+/// Parse the options of a command, returning `None` when its help was
+/// requested (and printed).
+fn parse(command: &str, arguments: &str, args: &[String], mut opts: Vec<OptGroup>)
+         -> Result<Option<Matches>, Failure> {
+    opts.push(optflag("h", "help", "print this help"));
+    let matches = match getopts::getopts(args, opts.as_slice()) {
+        Ok(matches) => matches,
+        Err(err) => return Err(Failure::Usage(format!("{}: {}", command, err))),
+    };
+
+    if matches.opt_present("h") {
+        let brief = format!("Usage: markovgen {} [options] {}", command, arguments);
+        println!("{}", getopts::usage(brief.as_slice(), opts.as_slice()));
+        return Ok(None);
+    }
+
+    Ok(Some(matches))
+}
+
+/// Value of the option `name`, or `default` when absent.
+fn number<T: FromStr>(matches: &Matches, name: &str, default: T) -> Result<T, Failure> {
+    match matches.opt_str(name) {
+        Some(value) => match from_str(value.as_slice()) {
+            Some(number) => Ok(number),
+            None => Err(Failure::Usage(format!("invalid value `{}` for --{}", value, name))),
+        },
+        None => Ok(default),
+    }
+}
+
+fn load(path: &str) -> Result<Model, Failure> {
+    MarkovGenerator::load_from_file(&Path::new(path), HashMap::new()).map_err(|err| {
+        Failure::Error(format!("{}: {}", path, err))
+    })
+}
+
+/// The single model argument of a command.
+fn model_argument(command: &str, matches: &Matches) -> Result<Model, Failure> {
+    match matches.free.as_slice() {
+        [ref path] => load(path.as_slice()),
+        [] => Err(Failure::Usage(format!("{}: missing model file", command))),
+        _ => Err(Failure::Usage(format!("{}: expected a single model file", command))),
+    }
+}
+
+fn output(matches: &Matches) -> Result<Box<Writer + 'static>, Failure> {
+    match matches.opt_str("o") {
+        Some(path) => match File::create(&Path::new(path.as_slice())) {
+            Ok(file) => Ok(box file as Box<Writer>),
+            Err(err) => Err(Failure::Error(format!("{}: {}", path, err))),
+        },
+        None => Ok(box io::stdout() as Box<Writer>),
+    }
+}
+
+fn write_failure(err: io::IoError) -> Failure {
+    Failure::Error(format!("cannot write output: {}", err))
+}
+
+fn train(args: &[String]) -> Result<(), Failure> {
+    let matches = match try!(parse("train", "<file|directory|pattern>...", args, vec![
+        optopt("n", "order", "number of words in a state (default: 2)", "N"),
+        optopt("t", "tokenizer", "whitespace, word-punct, characters or graphemes", "NAME"),
+        optflag("s", "sentences", "wrap sentences in start and end markers"),
+        optflag("b", "backoff", "also store lower orders and back off to them"),
+        optopt("o", "output", "file to write the model to", "FILE"),
+        optflag("q", "quiet", "do not report progress"),
+        optopt("", "csv-column", "feed the named column of CSV files, one document per row", "NAME"),
+        optopt("", "json-field", "feed a dotted field path of JSON Lines files, one document per line", "PATH"),
+        optflag("", "chat-log", "feed the messages of IRC or Matrix logs, one document per message"),
+    ])) {
+        Some(matches) => matches,
+        None => return Ok(()),
+    };
+
+    let order = try!(number(&matches, "order", 2u));
+    let path = match matches.opt_str("o") {
+        Some(path) => path,
+        None => return Err(Failure::Usage("train: missing --output".to_string())),
+    };
+    if matches.free.is_empty() {
+        return Err(Failure::Usage("train: missing corpus file".to_string()));
+    }
+
+    let mut markov = if matches.opt_present("b") {
+        MarkovGenerator::with_backoff(HashMap::new(), order)
+    } else {
+        MarkovGenerator::with_order(HashMap::new(), order)
+    };
+    match matches.opt_str("t") {
+        Some(name) => match tokenizer_by_name(name.as_slice()) {
+            Some(tokenizer) => markov.tokenizer = tokenizer,
+            None => return Err(Failure::Usage(format!("train: unknown tokenizer `{}`", name))),
+        },
+        None => {}
+    }
+    markov.sentences = matches.opt_present("s");
+
+    let mut formats = Vec::new();
+    match matches.opt_str("csv-column") {
+        Some(column) => formats.push(RecordFormat::Csv(column)),
+        None => {}
+    }
+    match matches.opt_str("json-field") {
+        Some(path) => formats.push(RecordFormat::JsonLines(path)),
+        None => {}
+    }
+    if matches.opt_present("chat-log") {
+        formats.push(RecordFormat::ChatLog);
+    }
+    if formats.len() > 1 {
+        return Err(Failure::Usage("train: --csv-column, --json-field and --chat-log are exclusive".to_string()));
+    }
+
+    let quiet = matches.opt_present("q");
+    let specs: Vec<&str> = matches.free.iter().map(|spec| spec.as_slice()).collect();
+    let mut stderr = io::stderr();
+    let summary = {
+        let progress = |path: &Path, fed: &FeedSummary| {
+            if !quiet {
+                let _ = writeln!(&mut stderr, "{}: {} lines, {} tokens", path.display(), fed.lines, fed.tokens);
+            }
+        };
+        let fed = match formats.head() {
+            Some(format) => markov.feed_records_from_corpus(specs.as_slice(), format, progress),
+            None => markov.feed_from_corpus(specs.as_slice(), progress),
+        };
+        try!(fed.map_err(|err| Failure::Error(err.to_string())))
+    };
+    if !quiet {
+        let _ = writeln!(&mut stderr, "fed {} files, {} lines, {} tokens",
+                         summary.files, summary.lines, summary.tokens);
+    }
+
+    markov.save_to_file(&Path::new(path.as_slice())).map_err(|err| {
+        Failure::Error(format!("{}: {}", path, err))
+    })
+}
+
+fn generate(args: &[String]) -> Result<(), Failure> {
+    let matches = match try!(parse("generate", "<model>", args, vec![
+        optopt("w", "words", "number of words per sample, or per sentence (default: 30)", "N"),
+        optopt("s", "sentences", "generate N sentences per sample instead of words", "N"),
+        optopt("c", "count", "number of samples (default: 1)", "N"),
+        optopt("S", "seed", "seed of the random generator", "SEED"),
+        optopt("o", "output", "file to write the samples to", "FILE"),
+    ])) {
+        Some(matches) => matches,
+        None => return Ok(()),
+    };
+
+    let words = try!(number(&matches, "words", 30u));
+    let sentences = try!(number(&matches, "sentences", 0u));
+    let count = try!(number(&matches, "count", 1u));
+    let seed = try!(number(&matches, "seed", task_rng().gen::<u64>()));
+    let markov = try!(model_argument("generate", &matches));
+    if sentences > 0 && !markov.sentences {
+        return Err(Failure::Error("the model was not trained with sentence markers".to_string()));
+    }
+
+    let mut rng = seeded_rng(seed);
+    let mut writer = try!(output(&matches));
+    for _ in range(0, count) {
+        let text = if sentences > 0 {
+            let sentences: Vec<String> = range(0, sentences).map(|_| {
+                markov.generate_sentence_with_rng(words, &mut rng)
+            }).collect();
+            sentences.connect(" ")
+        } else {
+            markov.generate_text_with_rng(words, &mut rng)
+        };
+        try!(writeln!(writer, "{}", text).map_err(write_failure));
+    }
+
+    Ok(())
+}
+
+fn score(args: &[String]) -> Result<(), Failure> {
+    let matches = match try!(parse("score", "<model> [text]...", args, vec![
+        optopt("d", "discount", "Kneser-Ney discount (default: 0.75)", "D"),
+    ])) {
+        Some(matches) => matches,
+        None => return Ok(()),
+    };
+
+    let discount = try!(number(&matches, "discount", 0.75f64));
+    if !Smoothing::KneserNey(discount).is_valid() {
+        return Err(Failure::Usage(format!("score: the discount must be between 0 and 1, not {}", discount)));
+    }
+    let path = match matches.free.as_slice().head() {
+        Some(path) => path,
+        None => return Err(Failure::Usage("score: missing model file".to_string())),
+    };
+    let markov = try!(load(path.as_slice()));
+    let scorer = markov.scorer(Smoothing::KneserNey(discount));
+
+    // Score the texts given as arguments, or every line of the standard input.
+    let mut texts = matches.free[1..].to_vec();
+    if texts.is_empty() {
+        for line in io::stdin().lines() {
+            match line {
+                Ok(line) => texts.push(line.as_slice().trim_right_chars(['\r', '\n'].as_slice()).to_string()),
+                Err(err) => return Err(Failure::Error(format!("cannot read input: {}", err))),
+            }
+        }
+    }
+
+    let mut stdout = io::stdout();
+    for text in texts.iter() {
+        let tokens = markov.tokenizer.tokenize(text.as_slice());
+        let score = scorer.score(tokens.as_slice());
+        try!(writeln!(&mut stdout, "{:.4}\t{:.4}\t{}", score.log_prob, score.perplexity, text)
+             .map_err(write_failure));
+    }
+
+    Ok(())
+}
+
+fn stats(args: &[String]) -> Result<(), Failure> {
+    let matches = match try!(parse("stats", "<model>", args, Vec::new())) {
+        Some(matches) => matches,
+        None => return Ok(()),
+    };
+    let markov = try!(model_argument("stats", &matches));
+
+    let (mut states, mut transitions, mut total) = (0u, 0u, 0u64);
+    for (_, successors) in markov.cache.states() {
+        states += 1;
+        transitions += successors.len();
+        total += successors.iter().fold(0u64, |total, transition| total + transition.count as u64);
+    }
+
+    println!("order:       {}", markov.order);
+    println!("min order:   {}", markov.min_order);
+    println!("tokenizer:   {}", markov.tokenizer.name());
+    println!("sentences:   {}", markov.sentences);
+    println!("vocabulary:  {}", markov.symbols.len());
+    println!("words:       {}", markov.words().len());
+    println!("states:      {}", states);
+    println!("transitions: {}", transitions);
+    println!("total count: {}", total);
+    Ok(())
+}
+
+fn inspect(args: &[String]) -> Result<(), Failure> {
+    let matches = match try!(parse("inspect", "<model> <word>...", args, vec![
+        optopt("l", "limit", "only show the N most frequent successors", "N"),
+    ])) {
+        Some(matches) => matches,
+        None => return Ok(()),
+    };
+
+    let limit = try!(number(&matches, "limit", 0u));
+    let path = match matches.free.as_slice().head() {
+        Some(path) => path,
+        None => return Err(Failure::Usage("inspect: missing model file".to_string())),
+    };
+    let markov = try!(load(path.as_slice()));
+
+    let words = matches.free[1..];
+    if words.len() < markov.min_order || words.len() > markov.order {
+        return Err(Failure::Usage(format!("inspect: the model has states of {} to {} words, not {}",
+                                          markov.min_order, markov.order, words.len())));
+    }
+
+    let mut key = Vec::with_capacity(words.len());
+    for word in words.iter() {
+        match markov.symbols.get(word.as_slice()) {
+            Some(symbol) => key.push(symbol),
+            None => return Err(Failure::Error(format!("`{}` was never fed to the model", word))),
+        }
+    }
+
+    let mut successors = match markov.cache.get(key.as_slice()) {
+        Some(successors) => successors.to_vec(),
+        None => return Err(Failure::Error(format!("unknown state `{}`", words.connect(" ")))),
+    };
+    successors.sort_by(|a, b| b.count.cmp(&a.count));
+    if limit > 0 {
+        successors.truncate(limit);
+    }
 
-    let mut markov = MarkovGenerator::new(HashMap::new());
-    markov.feed_from_file(&path);
+    let total = markov.cache.get(key.as_slice()).unwrap().iter()
+                              .fold(0u64, |total, transition| total + transition.count as u64);
+    for transition in successors.iter() {
+        println!("{}\t{:.4}\t{}", transition.count, transition.count as f64 / total as f64,
+                 markov.symbols.resolve(transition.symbol));
+    }
 
-    let text = markov.generate_text(30);
-    println!("{}", text);
+    Ok(())
 }
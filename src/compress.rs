@@ -0,0 +1,248 @@
+/*!
+ * Transparent decompression of corpus files.
+ *
+ * The format is detected from the magic bytes of the file, or from its
+ * extension when they match no known format. Each format needs its cargo
+ * feature:
+ *
+ * ```text
+ * gzip     .gz     decoded by the `flate2` crate
+ * zstd     .zst    decoded by the `zstd` command, which must be installed
+ * xz       .xz     decoded by the `xz` command, which must be installed
+ * bzip2    .bz2    decoded by the `bzip2` crate
+ * ```
+ *
+ * Compressed files are decoded as a stream while they are fed.
+ */
+
+#[cfg(feature = "bzip2")]
+use bzip2::reader::BzDecompressor;
+#[cfg(feature = "gzip")]
+use flate2::reader::GzDecoder;
+use std::io::{
+    File,
+    IoErrorKind,
+    IoResult,
+};
+#[cfg(any(feature = "zstd", feature = "xz"))]
+use std::io::IoError;
+#[cfg(any(feature = "zstd", feature = "xz"))]
+use std::io::process::{
+    Command,
+    InheritFd,
+    Process,
+};
+
+use {
+    FeedError,
+    FeedErrorKind,
+};
+/// Compression format of a corpus file.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Compression {
+    Plain,
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2,
+}
+
+impl Compression {
+    /// Detect the format of the file at `path`.
+    pub fn detect(path: &Path) -> Result<Compression, FeedError> {
+        let mut file = try!(File::open(path));
+        let mut magic = [0u8, ..10];
+        let len = try!(read_prefix(&mut file, &mut magic));
+
+        let compression = match Compression::from_magic(magic[..len]) {
+            Some(compression) => compression,
+            None => match path.extension_str() {
+                Some("gz") => Compression::Gzip,
+                Some("zst") => Compression::Zstd,
+                Some("xz") => Compression::Xz,
+                Some("bz2") => Compression::Bzip2,
+                _ => Compression::Plain,
+            },
+        };
+
+        Ok(compression)
+    }
+
+    /// Format whose magic bytes start `magic`, if any.
+    fn from_magic(magic: &[u8]) -> Option<Compression> {
+        if magic.starts_with(&[0x1f, 0x8b, 0x08]) {
+            Some(Compression::Gzip)
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Compression::Xz)
+        } else if is_bzip2(magic) {
+            Some(Compression::Bzip2)
+        } else {
+            None
+        }
+    }
+
+    /// Name of the cargo feature (and of the command) handling the format.
+    fn name(&self) -> &'static str {
+        match *self {
+            Compression::Plain => "plain",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::Xz => "xz",
+            Compression::Bzip2 => "bzip2",
+        }
+    }
+}
+
+/// Whether `magic` starts a bzip2 stream: `BZh`, the block size digit, then
+/// the magic of the first block, or of the end of an empty stream.
+fn is_bzip2(magic: &[u8]) -> bool {
+    const BLOCK: [u8, ..6] = [0x31, 0x41, 0x59, 0x26, 0x53, 0x59];
+    const END: [u8, ..6] = [0x17, 0x72, 0x45, 0x38, 0x50, 0x90];
+
+    magic.len() >= 10 && magic.starts_with(b"BZh") && magic[3] >= b'1' && magic[3] <= b'9' &&
+        (magic[4..10] == BLOCK.as_slice() || magic[4..10] == END.as_slice())
+}
+
+/// Fill `buf` from the start of `reader`, returning the number of bytes read,
+/// fewer only at the end of the file.
+fn read_prefix<R: Reader>(reader: &mut R, buf: &mut [u8]) -> IoResult<uint> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(buf[mut len..]) {
+            Ok(read) => len += read,
+            Err(ref err) if err.kind == IoErrorKind::EndOfFile => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(len)
+}
+
+/// Open the file at `path`, decoding it as it is read if it is compressed.
+pub fn open(path: &Path) -> Result<Box<Reader + 'static>, FeedError> {
+    let compression = try!(Compression::detect(path));
+    let reader = match compression {
+        Compression::Plain => Some(Ok(box try!(File::open(path)) as Box<Reader + 'static>)),
+        Compression::Gzip => gzip_reader(path),
+        Compression::Zstd | Compression::Xz => command_reader(compression.name(), path),
+        Compression::Bzip2 => bzip2_reader(path),
+    };
+    debug!("Reading {} as {}", path.display(), compression.name());
+
+    match reader {
+        Some(Ok(reader)) => Ok(reader),
+        Some(Err(err)) => Err(error(format!("cannot read {} input: {}", compression.name(), err))),
+        None => Err(error(format!("built without the `{}` feature for compressed input", compression.name()))),
+    }
+}
+
+fn error(message: String) -> FeedError {
+    FeedError {
+        kind: FeedErrorKind::Compression(message),
+        line: 0,
+        offset: 0,
+    }
+}
+
+#[cfg(feature = "gzip")]
+fn gzip_reader(path: &Path) -> Option<IoResult<Box<Reader + 'static>>> {
+    Some(File::open(path).and_then(GzDecoder::new).map(|reader| box reader as Box<Reader + 'static>))
+}
+
+#[cfg(not(feature = "gzip"))]
+fn gzip_reader(_: &Path) -> Option<IoResult<Box<Reader + 'static>>> {
+    None
+}
+
+#[cfg(feature = "bzip2")]
+fn bzip2_reader(path: &Path) -> Option<IoResult<Box<Reader + 'static>>> {
+    Some(File::open(path).map(|file| box BzDecompressor::new(file) as Box<Reader + 'static>))
+}
+
+#[cfg(not(feature = "bzip2"))]
+fn bzip2_reader(_: &Path) -> Option<IoResult<Box<Reader + 'static>>> {
+    None
+}
+
+/// Run `command -dc path` and read its output, if the feature of `command`
+/// is enabled.
+#[cfg(any(feature = "zstd", feature = "xz"))]
+fn command_reader(command: &str, path: &Path) -> Option<IoResult<Box<Reader + 'static>>> {
+    let enabled = match command {
+        "zstd" => cfg!(feature = "zstd"),
+        _ => cfg!(feature = "xz"),
+    };
+    if !enabled {
+        return None;
+    }
+
+    let process = Command::new(command).arg("-dc").arg(path).stderr(InheritFd(2)).spawn();
+    Some(process.map(|process| box CommandReader {
+        command: command.to_string(),
+        process: process,
+    } as Box<Reader + 'static>))
+}
+
+#[cfg(not(any(feature = "zstd", feature = "xz")))]
+fn command_reader(_: &str, _: &Path) -> Option<IoResult<Box<Reader + 'static>>> {
+    None
+}
+
+/// Output of a decoding command, which fails at the end if the command did.
+#[cfg(any(feature = "zstd", feature = "xz"))]
+struct CommandReader {
+    command: String,
+    process: Process,
+}
+
+#[cfg(any(feature = "zstd", feature = "xz"))]
+impl Reader for CommandReader {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        let result = match self.process.stdout {
+            Some(ref mut stdout) => stdout.read(buf),
+            None => unreachable!(),
+        };
+
+        match result {
+            Err(ref err) if err.kind == IoErrorKind::EndOfFile => {
+                let status = try!(self.process.wait());
+                if !status.success() {
+                    return Err(IoError {
+                        kind: IoErrorKind::OtherIoError,
+                        desc: "corrupted compressed input",
+                        detail: Some(format!("`{}` failed ({})", self.command, status)),
+                    });
+                }
+            }
+            _ => {}
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Compression,
+        is_bzip2,
+    };
+
+    #[test]
+    fn magic_bytes() {
+        assert_eq!(Compression::from_magic(&[0x1f, 0x8b, 0x08, 0x00]), Some(Compression::Gzip));
+        assert_eq!(Compression::from_magic(&[0x28, 0xb5, 0x2f, 0xfd, 0x00]), Some(Compression::Zstd));
+        assert_eq!(Compression::from_magic(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]), Some(Compression::Xz));
+        assert_eq!(Compression::from_magic(b"plain text"), None);
+        assert_eq!(Compression::from_magic(&[]), None);
+    }
+
+    #[test]
+    fn bzip2_needs_the_block_magic() {
+        assert!(is_bzip2(b"BZh91AY&SY"));
+        assert!(is_bzip2(b"BZh1\x17\x72\x45\x38\x50\x90"));
+        assert!(!is_bzip2(b"BZh01AY&SY"));
+        assert!(!is_bzip2(b"BZhello world"));
+        assert!(!is_bzip2(b"BZh9"));
+    }
+}
@@ -0,0 +1,271 @@
+/*!
+ * Expansion of corpus specifications into the files to feed.
+ *
+ * A specification is a file, a directory (every file below it, recursively)
+ * or a glob pattern, whose components may use `*`, `?`, `[abc]`, `[a-z]`,
+ * `[!abc]` and `**` (any number of nested directories). Matched directories
+ * are walked recursively too, each directory once even when symbolic links
+ * make cycles. A specification naming an existing file is never taken as a
+ * pattern, e.g. `notes[1].txt`.
+ */
+
+use std::collections::HashSet;
+use std::io::{
+    fs,
+    IoError,
+    IoErrorKind,
+    IoResult,
+};
+use std::io::fs::PathExtensions;
+
+/// Expand `specs` into the files they name, those of each specification
+/// sorted, without the files already named by an earlier one. A
+/// specification matching no file is an error.
+pub fn corpus_files(specs: &[&str]) -> IoResult<Vec<Path>> {
+    let mut files = Vec::new();
+    for &spec in specs.iter() {
+        let mut matched = Vec::new();
+        if is_pattern(spec) && !Path::new(spec).exists() {
+            try!(expand_pattern(spec, &mut matched));
+        } else {
+            try!(expand_path(Path::new(spec), &mut matched));
+        }
+
+        if matched.is_empty() {
+            return Err(IoError {
+                kind: IoErrorKind::FileNotFound,
+                desc: "no file matches the corpus specification",
+                detail: Some(spec.to_string()),
+            });
+        }
+
+        matched.sort_by(|a, b| a.as_vec().cmp(b.as_vec()));
+        files.extend(matched.into_iter());
+    }
+
+    Ok(remove_duplicates(files))
+}
+
+/// Keep only the first occurrence of each path, e.g. of a file named by both
+/// its directory and a pattern.
+pub fn remove_duplicates(paths: Vec<Path>) -> Vec<Path> {
+    let mut seen = HashSet::new();
+    paths.into_iter().filter(|path| seen.insert(path.as_vec().to_vec())).collect()
+}
+
+fn is_pattern(spec: &str) -> bool {
+    spec.contains_char('*') || spec.contains_char('?') || spec.contains_char('[')
+}
+
+/// Add `path` if it is a file, or every file below it if it is a directory.
+fn expand_path(path: Path, files: &mut Vec<Path>) -> IoResult<()> {
+    if path.is_dir() {
+        for dir in try!(directories(&path)).iter() {
+            for entry in try!(fs::readdir(dir)).into_iter() {
+                if entry.is_file() {
+                    files.push(entry);
+                }
+            }
+        }
+    } else if path.exists() {
+        files.push(path);
+    } else {
+        try!(fs::stat(&path));
+    }
+
+    Ok(())
+}
+
+fn expand_pattern(pattern: &str, files: &mut Vec<Path>) -> IoResult<()> {
+    let mut candidates = vec![Path::new(if pattern.starts_with("/") { "/" } else { "." })];
+
+    for component in pattern.split('/').filter(|component| !component.is_empty()) {
+        let mut next = Vec::new();
+        for dir in candidates.iter() {
+            if component == "**" {
+                if dir.is_dir() {
+                    next.extend(try!(directories(dir)).into_iter());
+                }
+            } else if is_pattern(component) {
+                if dir.is_dir() {
+                    let pattern: Vec<char> = component.chars().collect();
+                    for entry in try!(fs::readdir(dir)).into_iter() {
+                        let matched = match entry.filename_str() {
+                            Some(name) => {
+                                let name: Vec<char> = name.chars().collect();
+                                matches(pattern.as_slice(), name.as_slice())
+                            }
+                            None => false,
+                        };
+                        if matched {
+                            next.push(entry);
+                        }
+                    }
+                }
+            } else {
+                next.push(dir.join(component));
+            }
+        }
+        candidates = next;
+    }
+
+    for path in candidates.into_iter() {
+        if path.exists() {
+            try!(expand_path(path, files));
+        }
+    }
+
+    Ok(())
+}
+
+/// Every directory below `dir`, `dir` included, following symbolic links
+/// but visiting each directory once.
+fn directories(dir: &Path) -> IoResult<Vec<Path>> {
+    let mut visited = HashSet::new();
+    let mut pending = vec![dir.clone()];
+    let mut dirs = Vec::new();
+
+    loop {
+        let dir = match pending.pop() {
+            Some(dir) => dir,
+            None => break,
+        };
+        let stat = try!(fs::stat(&dir));
+        if !visited.insert((stat.unstable.device, stat.unstable.inode)) {
+            continue;
+        }
+
+        for entry in try!(fs::readdir(&dir)).into_iter() {
+            if entry.is_dir() {
+                pending.push(entry);
+            }
+        }
+        dirs.push(dir);
+    }
+
+    Ok(dirs)
+}
+
+/// Whether the file name `name` matches the glob component `pattern`.
+fn matches(pattern: &[char], name: &[char]) -> bool {
+    match pattern.head() {
+        None => name.is_empty(),
+        Some(&'*') => range(0, name.len() + 1).any(|skip| matches(pattern[1..], name[skip..])),
+        Some(&'?') => !name.is_empty() && matches(pattern[1..], name[1..]),
+        Some(&'[') if !name.is_empty() => {
+            let negated = pattern.len() > 1 && (pattern[1] == '!' || pattern[1] == '^');
+            let start = if negated { 2 } else { 1 };
+
+            // A `]` right after the opening bracket belongs to the set.
+            match pattern.iter().skip(start + 1).position(|&c| c == ']') {
+                Some(position) => {
+                    let end = start + 1 + position;
+                    in_class(pattern[start..end], name[0]) != negated && matches(pattern[end + 1..], name[1..])
+                }
+                None => name[0] == '[' && matches(pattern[1..], name[1..]),
+            }
+        }
+        Some(&c) => name.head() == Some(&c) && matches(pattern[1..], name[1..]),
+    }
+}
+
+fn in_class(class: &[char], c: char) -> bool {
+    let mut index = 0;
+    while index < class.len() {
+        if index + 2 < class.len() && class[index + 1] == '-' {
+            if class[index] <= c && c <= class[index + 2] {
+                return true;
+            }
+            index += 3;
+        } else {
+            if class[index] == c {
+                return true;
+            }
+            index += 1;
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{
+        fs,
+        File,
+        TempDir,
+        USER_RWX,
+    };
+
+    use super::{
+        corpus_files,
+        in_class,
+        is_pattern,
+        matches,
+    };
+
+    fn glob(pattern: &str, name: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let name: Vec<char> = name.chars().collect();
+        matches(pattern.as_slice(), name.as_slice())
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(glob("*.txt", "notes.txt"));
+        assert!(glob("*.txt", ".txt"));
+        assert!(!glob("*.txt", "notes.txt.gz"));
+        assert!(glob("n*s*.txt", "notes.txt"));
+        assert!(glob("?otes.txt", "notes.txt"));
+        assert!(!glob("?notes.txt", "notes.txt"));
+        assert!(glob("*", ""));
+        assert!(!glob("?", ""));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(glob("[nm]otes", "notes"));
+        assert!(!glob("[!nm]otes", "notes"));
+        assert!(glob("[^nm]otes", "votes"));
+        assert!(glob("part[0-9].txt", "part7.txt"));
+        assert!(!glob("part[0-9].txt", "partx.txt"));
+        assert!(glob("[]]", "]"));
+        // An unterminated class matches a literal bracket.
+        assert!(glob("[abc", "[abc"));
+        assert!(!glob("[abc", "a"));
+    }
+
+    #[test]
+    fn in_class_ranges_and_literals() {
+        let class: Vec<char> = "a-cx-".chars().collect();
+        assert!(in_class(class.as_slice(), 'b'));
+        assert!(in_class(class.as_slice(), 'x'));
+        assert!(in_class(class.as_slice(), '-'));
+        assert!(!in_class(class.as_slice(), 'd'));
+        assert!(!in_class(&[], 'a'));
+    }
+
+    #[test]
+    fn patterns() {
+        assert!(is_pattern("corpus/*.txt"));
+        assert!(is_pattern("notes[1].txt"));
+        assert!(!is_pattern("corpus/notes.txt"));
+    }
+
+    #[test]
+    fn files_named_twice_are_fed_once() {
+        let dir = TempDir::new("markov-corpus").unwrap();
+        fs::mkdir(&dir.path().join("sub"), USER_RWX).unwrap();
+        for name in ["a.txt", "b.txt", "sub/c.txt"].iter() {
+            File::create(&dir.path().join(*name)).unwrap();
+        }
+
+        let root = dir.path().as_str().unwrap();
+        let pattern = format!("{}/*.txt", root);
+        let nested = format!("{}/sub/c.txt", root);
+        let files = corpus_files(&[nested.as_slice(), root, pattern.as_slice()]).unwrap();
+
+        let names: Vec<&str> = files.iter().map(|path| path.filename_str().unwrap()).collect();
+        assert_eq!(names, vec!["c.txt", "a.txt", "b.txt"]);
+    }
+}
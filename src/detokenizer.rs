@@ -0,0 +1,142 @@
+/// Turns generated tokens back into text, the counterpart of a `Tokenizer`.
+pub trait Detokenizer {
+    fn detokenize(&self, tokens: &[&str]) -> String;
+}
+
+/// Join tokens with a single space.
+#[deriving(Clone, Show)]
+pub struct Spaced;
+
+/// Join tokens without any separator, for character-level models.
+#[deriving(Clone, Show)]
+pub struct Concat;
+
+/// Join words with spaces but attach punctuation, quotes and brackets to
+/// their neighbours, and capitalize the start of each sentence.
+#[deriving(Clone, Show)]
+pub struct Prose;
+
+impl Detokenizer for Spaced {
+    fn detokenize(&self, tokens: &[&str]) -> String {
+        tokens.connect(" ")
+    }
+}
+
+impl Detokenizer for Concat {
+    fn detokenize(&self, tokens: &[&str]) -> String {
+        tokens.concat()
+    }
+}
+
+#[deriving(PartialEq)]
+enum Kind {
+    Word,
+    Opening,
+    Closing,
+}
+
+fn is_closing(c: char) -> bool {
+    ['.', ',', ';', ':', '!', '?', '%', ')', ']', '}', '…', '»'].contains(&c)
+}
+
+fn is_opening(c: char) -> bool {
+    ['(', '[', '{', '¿', '¡', '«'].contains(&c)
+}
+
+fn is_sentence_end(c: char) -> bool {
+    ['.', '!', '?', '…'].contains(&c)
+}
+
+/// Han, kana and hangul are written without spaces between words.
+fn is_unspaced_script(c: char) -> bool {
+    match c {
+        '぀'...'ヿ' | '㐀'...'䶿' | '一'...'鿿' | '가'...'힯' => true,
+        '　'...'〿' | '＀'...'￯' => true,
+        _ => false,
+    }
+}
+
+impl Detokenizer for Prose {
+    fn detokenize(&self, tokens: &[&str]) -> String {
+        let mut text = String::new();
+        let mut attach_next = true;
+        let mut capitalize = true;
+        let mut double_quote_open = false;
+        let mut single_quote_open = false;
+
+        for &token in tokens.iter() {
+            let first = match token.chars().next() {
+                Some(c) => c,
+                None => continue,
+            };
+
+            let kind = match token {
+                "\"" => {
+                    double_quote_open = !double_quote_open;
+                    if double_quote_open { Kind::Opening } else { Kind::Closing }
+                }
+                "'" => {
+                    single_quote_open = !single_quote_open;
+                    if single_quote_open { Kind::Opening } else { Kind::Closing }
+                }
+                _ if token.chars().all(is_closing) => Kind::Closing,
+                _ if token.chars().all(is_opening) => Kind::Opening,
+                _ => Kind::Word,
+            };
+
+            let unspaced = text.chars().next_back().map_or(false, is_unspaced_script)
+                           && is_unspaced_script(first);
+            if !attach_next && kind != Kind::Closing && !unspaced {
+                text.push(' ');
+            }
+
+            if capitalize && kind == Kind::Word && first.is_lowercase() {
+                text.push(first.to_uppercase());
+                text.push_str(token[first.len_utf8()..]);
+            } else {
+                text.push_str(token);
+            }
+
+            attach_next = kind == Kind::Opening;
+            if kind == Kind::Word {
+                capitalize = false;
+            }
+            if token.chars().next_back().map_or(false, is_sentence_end) {
+                capitalize = true;
+            }
+        }
+
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Detokenizer,
+        Prose,
+    };
+
+    fn prose(tokens: &[&str]) -> String {
+        Prose.detokenize(tokens)
+    }
+
+    #[test]
+    fn attaches_punctuation_and_capitalizes_sentences() {
+        assert_eq!(prose(&["hello", ",", "world", "."]), "Hello, world.".to_string());
+        assert_eq!(prose(&["(", "yes", ")", "ok", "?", "sure"]), "(Yes) ok? Sure".to_string());
+        assert_eq!(prose(&["a", "", "b"]), "A b".to_string());
+    }
+
+    #[test]
+    fn pairs_quotes() {
+        assert_eq!(prose(&["he", "said", "\"", "hi", "\"", "."]), "He said \"hi\".".to_string());
+        assert_eq!(prose(&["a", "'", "b", "'", "c"]), "A 'b' c".to_string());
+    }
+
+    #[test]
+    fn unspaced_scripts() {
+        assert_eq!(prose(&["日本", "語", "。"]), "日本語。".to_string());
+        assert_eq!(prose(&["東京", "is", "big"]), "東京 is big".to_string());
+    }
+}
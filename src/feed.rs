@@ -0,0 +1,278 @@
+use std::error::{
+    Error,
+    FromError,
+};
+use std::default::Default;
+use std::fmt;
+use std::io::{
+    BufferedReader,
+    IoError,
+    IoErrorKind,
+};
+
+use compress;
+use corpus::remove_duplicates;
+use records::read_records;
+use {
+    corpus_files,
+    CacheMut,
+    Interner,
+    MarkovGenerator,
+    RecordFormat,
+};
+
+/// What to do with input lines that are not valid UTF-8.
+#[deriving(Clone, PartialEq, Show)]
+pub enum InvalidInput {
+    /// Stop feeding and return an error.
+    Abort,
+    /// Ignore the whole line.
+    Skip,
+    /// Replace invalid sequences with U+FFFD.
+    Replace,
+}
+
+#[deriving(Show)]
+pub enum FeedErrorKind {
+    Io(IoError),
+    InvalidUtf8,
+    /// The input is compressed in an unsupported format, or corrupted.
+    Compression(String),
+    /// A structured record could not be parsed.
+    Malformed(String),
+}
+
+/// Error raised while feeding, with the line (starting at 1) and the byte
+/// offset of the start of that line, both 0 for errors about the whole
+/// input.
+pub struct FeedError {
+    pub kind: FeedErrorKind,
+    pub line: uint,
+    pub offset: u64,
+}
+
+impl fmt::Show for FeedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            FeedErrorKind::Io(ref err) => try!(write!(f, "{}", err)),
+            FeedErrorKind::InvalidUtf8 => try!(write!(f, "invalid UTF-8")),
+            FeedErrorKind::Compression(ref message) => try!(write!(f, "{}", message)),
+            FeedErrorKind::Malformed(ref message) => try!(write!(f, "{}", message)),
+        }
+        if self.line == 0 {
+            return Ok(());
+        }
+        write!(f, " at line {} (byte {})", self.line, self.offset)
+    }
+}
+
+impl Error for FeedError {
+    fn description(&self) -> &str {
+        match self.kind {
+            FeedErrorKind::Io(ref err) => err.description(),
+            FeedErrorKind::InvalidUtf8 => "invalid UTF-8",
+            FeedErrorKind::Compression(..) => "compressed input error",
+            FeedErrorKind::Malformed(..) => "malformed record",
+        }
+    }
+
+    fn detail(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match self.kind {
+            FeedErrorKind::Io(ref err) => Some(err as &Error),
+            _ => None,
+        }
+    }
+}
+
+impl FromError<IoError> for FeedError {
+    fn from_error(err: IoError) -> FeedError {
+        FeedError {
+            kind: FeedErrorKind::Io(err),
+            line: 0,
+            offset: 0,
+        }
+    }
+}
+
+/// Error raised while feeding a corpus, with the file it happened in.
+pub struct CorpusError {
+    pub path: Path,
+    pub error: FeedError,
+}
+
+impl fmt::Show for CorpusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.error)
+    }
+}
+
+impl Error for CorpusError {
+    fn description(&self) -> &str {
+        self.error.description()
+    }
+
+    fn detail(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        Some(&self.error as &Error)
+    }
+}
+
+/// What was consumed while feeding files.
+#[deriving(Clone, Default, PartialEq, Show)]
+pub struct FeedSummary {
+    pub files: uint,
+    pub lines: uint,
+    /// Number of fed tokens, markers excluded.
+    pub tokens: uint,
+}
+
+impl<C> MarkovGenerator<C> where C: CacheMut {
+    /// Feed every line of `reader`, aborting on the first invalid line.
+    pub fn feed_from_reader<B: Buffer>(&mut self, reader: &mut B) -> Result<(), FeedError> {
+        self.feed_from_reader_with(reader, InvalidInput::Abort)
+    }
+
+    pub fn feed_from_reader_with<B: Buffer>(&mut self, reader: &mut B, invalid: InvalidInput)
+                                            -> Result<(), FeedError> {
+        self.feed_lines(reader, invalid).map(|_| ())
+    }
+
+    /// Feed every line of `reader`, returning the number of lines read.
+    fn feed_lines<B: Buffer>(&mut self, reader: &mut B, invalid: InvalidInput) -> Result<uint, FeedError> {
+        let mut line_number = 0u;
+        let mut offset = 0u64;
+
+        loop {
+            let bytes = match reader.read_until(b'\n') {
+                Ok(bytes) => bytes,
+                Err(ref err) if err.kind == IoErrorKind::EndOfFile => break,
+                Err(err) => return Err(FeedError {
+                    kind: FeedErrorKind::Io(err),
+                    line: line_number + 1,
+                    offset: offset,
+                }),
+            };
+            let start = offset;
+            line_number += 1;
+            offset += bytes.len() as u64;
+
+            let line = match String::from_utf8(bytes) {
+                Ok(line) => line,
+                Err(bytes) => match invalid {
+                    InvalidInput::Abort => return Err(FeedError {
+                        kind: FeedErrorKind::InvalidUtf8,
+                        line: line_number,
+                        offset: start,
+                    }),
+                    InvalidInput::Skip => {
+                        debug!("Skipped invalid line {}", line_number);
+                        continue;
+                    }
+                    InvalidInput::Replace => String::from_utf8_lossy(bytes.as_slice()).into_string(),
+                },
+            };
+
+            self.feed_from_str(line.as_slice());
+        }
+
+        Ok(line_number)
+    }
+
+    /// Feed every line of the file at `path`, decompressing it on the fly if
+    /// needed (see `Compression`).
+    pub fn feed_from_file(&mut self, path: &Path) -> Result<(), FeedError> {
+        self.feed_file_lines(path, None).map(|_| ())
+    }
+
+    /// Feed the records of the file at `path`, decompressing it on the fly
+    /// if needed, see `feed_records`.
+    pub fn feed_records_from_file(&mut self, path: &Path, format: &RecordFormat) -> Result<(), FeedError> {
+        self.feed_file_lines(path, Some(format)).map(|_| ())
+    }
+
+    /// Feed every file of `paths` in turn, each as its own document (see
+    /// `feed_document`), calling `progress` with the summary of each file
+    /// once it is fed.
+    pub fn feed_from_files(&mut self, paths: &[Path], progress: |&Path, &FeedSummary|)
+                           -> Result<FeedSummary, CorpusError> {
+        self.feed_files(paths, None, progress)
+    }
+
+    /// Feed the files named by `specs`: files, directories or glob patterns,
+    /// as described in `corpus_files`.
+    pub fn feed_from_corpus(&mut self, specs: &[&str], progress: |&Path, &FeedSummary|)
+                            -> Result<FeedSummary, CorpusError> {
+        let paths = try!(expand_specs(specs));
+        self.feed_files(paths.as_slice(), None, progress)
+    }
+
+    /// Like `feed_from_corpus`, but feed the text of every record of the
+    /// files as its own document, see `feed_records`.
+    pub fn feed_records_from_corpus(&mut self, specs: &[&str], format: &RecordFormat,
+                                    progress: |&Path, &FeedSummary|) -> Result<FeedSummary, CorpusError> {
+        let paths = try!(expand_specs(specs));
+        self.feed_files(paths.as_slice(), Some(format), progress)
+    }
+
+    fn feed_files(&mut self, paths: &[Path], format: Option<&RecordFormat>, progress: |&Path, &FeedSummary|)
+                  -> Result<FeedSummary, CorpusError> {
+        let mut total: FeedSummary = Default::default();
+
+        for path in paths.iter() {
+            self.end_document();
+            let fed = self.words.len();
+            let lines = try!(self.feed_file_lines(path, format).map_err(|err| CorpusError {
+                path: path.clone(),
+                error: err,
+            }));
+            self.end_document();
+
+            let summary = FeedSummary {
+                files: 1,
+                lines: lines,
+                tokens: self.words[fed..].iter().filter(|&&symbol| !Interner::is_marker(symbol)).count(),
+            };
+            progress(path, &summary);
+
+            total.files += 1;
+            total.lines += summary.lines;
+            total.tokens += summary.tokens;
+        }
+
+        Ok(total)
+    }
+
+    /// Feed the lines, or the records when `format` is set, of the file at
+    /// `path`, returning the number of lines read.
+    fn feed_file_lines(&mut self, path: &Path, format: Option<&RecordFormat>) -> Result<uint, FeedError> {
+        let mut reader = BufferedReader::new(try!(compress::open(path)));
+        match format {
+            Some(format) => read_records(&mut reader, format, |text| self.feed_document_from_str(text)),
+            None => self.feed_lines(&mut reader, InvalidInput::Abort),
+        }
+    }
+}
+
+/// Expand every specification like `corpus_files`, reporting the one that
+/// failed.
+fn expand_specs(specs: &[&str]) -> Result<Vec<Path>, CorpusError> {
+    let mut paths = Vec::new();
+    for &spec in specs.iter() {
+        match corpus_files(&[spec]) {
+            Ok(files) => paths.extend(files.into_iter()),
+            Err(err) => return Err(CorpusError {
+                path: Path::new(spec),
+                error: FromError::from_error(err),
+            }),
+        }
+    }
+
+    Ok(remove_duplicates(paths))
+}
@@ -0,0 +1,291 @@
+use std::cmp;
+use std::num::Float;
+use std::rand::{
+    task_rng,
+    Rng,
+};
+use std::u32;
+
+use {
+    CacheMut,
+    Interner,
+    MarkovGenerator,
+    Symbol,
+    DOCUMENT_BOUNDARY,
+    SENTENCE_END,
+    SENTENCE_START,
+};
+
+impl<C> MarkovGenerator<C> where C: CacheMut {
+    /// Remove the contribution of `words`, previously given to
+    /// `feed_from_words`, as if they had never been fed. The most recent
+    /// occurrence is removed and the words around it are chained together.
+    ///
+    /// Returns false if the words cannot be found in the fed words. See
+    /// `trim_words` for the counts left after trimming.
+    pub fn unfeed_from_words(&mut self, words: &[&str]) -> bool {
+        let mut symbols = Vec::with_capacity(words.len());
+        for &word in words.iter() {
+            match self.symbols.get(word) {
+                Some(symbol) => symbols.push(symbol),
+                None => return false,
+            }
+        }
+
+        let (mut start, mut end) = match self.find_fed(symbols.as_slice()) {
+            Some(span) => span,
+            None => return false,
+        };
+        if self.sentences {
+            while start > 0 && self.words[start - 1] == SENTENCE_START {
+                start -= 1;
+            }
+            if end < self.words.len() && self.words[end] == SENTENCE_END {
+                end += 1;
+            }
+        }
+        // Drop the boundary closing a whole document along with it.
+        if start == 0 || self.words[start - 1] == DOCUMENT_BOUNDARY {
+            while end < self.words.len() && self.words[end] == DOCUMENT_BOUNDARY {
+                end += 1;
+            }
+        }
+
+        // Uncount every window touching the span, then count the windows that
+        // now bridge the words around it.
+        for window_end in range(cmp::max(start, self.order), cmp::min(end + self.order, self.words.len())) {
+            let window = self.words[window_end - self.order..window_end + 1].to_vec();
+            self.record_window(window.as_slice(), true);
+        }
+
+        let tail = self.words[end..].to_vec();
+        self.words.truncate(start);
+        self.words.extend(tail.into_iter());
+        self.invalidate_positions();
+
+        for window_end in range(cmp::max(start, self.order), cmp::min(start + self.order, self.words.len())) {
+            let window = self.words[window_end - self.order..window_end + 1].to_vec();
+            self.record_window(window.as_slice(), false);
+        }
+
+        if self.seeds.is_some() {
+            self.rebuild_seeds();
+        }
+
+        true
+    }
+
+    /// Tokenize `text` with the generator's tokenizer and unfeed the tokens.
+    pub fn unfeed_from_str(&mut self, text: &str) -> bool {
+        let words = self.tokenizer.tokenize(text);
+        self.unfeed_from_words(words.as_slice())
+    }
+
+    /// Find the most recent span of the fed words made of `symbols`, possibly
+    /// with markers in between.
+    fn find_fed(&self, symbols: &[Symbol]) -> Option<(uint, uint)> {
+        if symbols.is_empty() {
+            return None;
+        }
+
+        'start: for start in range(0, self.words.len()).rev() {
+            let mut index = start;
+            let mut matched = 0;
+
+            while matched < symbols.len() {
+                if index >= self.words.len() {
+                    continue 'start;
+                }
+
+                let symbol = self.words[index];
+                if symbol == symbols[matched] {
+                    matched += 1;
+                } else if matched == 0 || !Interner::is_marker(symbol) || symbol == DOCUMENT_BOUNDARY {
+                    continue 'start;
+                }
+                index += 1;
+            }
+
+            return Some((start, index));
+        }
+
+        None
+    }
+
+    /// Multiply every transition count by `factor`, then drop the
+    /// transitions whose count is below `threshold` (or zero) and rebuild the
+    /// seeds. Returns the number of transitions dropped from `cache`.
+    ///
+    /// Counts are rounded up or down at random, in proportion to the
+    /// fraction, so that frequent small decays still shrink small counts.
+    /// The fed words are kept, see `trim_words` to bound them too.
+    pub fn decay(&mut self, factor: f64, threshold: u32) -> uint {
+        self.decay_with_rng(factor, threshold, &mut task_rng())
+    }
+
+    /// Like `decay`, rounding the counts with `rng`.
+    pub fn decay_with_rng<R: Rng>(&mut self, factor: f64, threshold: u32, rng: &mut R) -> uint {
+        let threshold = cmp::max(threshold, 1);
+        let mut dropped = 0;
+
+        self.cache.retain(|_, transition| {
+            transition.count = decayed(transition.count, factor, rng);
+            if transition.count < threshold {
+                dropped += 1;
+            }
+            transition.count >= threshold
+        });
+
+        match self.backward {
+            Some(ref mut backward) => backward.retain(|_, transition| {
+                transition.count = decayed(transition.count, factor, rng);
+                transition.count >= threshold
+            }),
+            None => {}
+        }
+
+        // Generation must not start from a state that was just dropped.
+        self.rebuild_seeds();
+
+        dropped
+    }
+
+    /// Forget the oldest fed words so that at most `max_words` remain, e.g.
+    /// along with `decay` to bound the memory of a long-running generator.
+    /// The transition counts are kept, but the dropped words can no longer
+    /// be unfed nor used as seeds. Returns the number of words dropped.
+    ///
+    /// The windows ending within the first `order` remaining words also
+    /// cover dropped ones, so unfeeding those words leaves the counts of
+    /// these windows in place.
+    pub fn trim_words(&mut self, max_words: uint) -> uint {
+        if self.words.len() <= max_words {
+            return 0;
+        }
+
+        let dropped = self.words.len() - max_words;
+        self.words = self.words[dropped..].to_vec();
+        self.invalidate_positions();
+
+        match self.seeds {
+            Some(ref mut seeds) => {
+                seeds.retain(|&seed| seed >= dropped);
+                for seed in seeds.iter_mut() {
+                    *seed -= dropped;
+                }
+            }
+            None => {}
+        }
+
+        dropped
+    }
+
+    /// Decay counts exponentially, halving them every `half_life` units of
+    /// time, `elapsed` being the time since the last decay (in the same unit).
+    pub fn decay_over(&mut self, elapsed: f64, half_life: f64, threshold: u32) -> uint {
+        self.decay(0.5f64.powf(elapsed / half_life), threshold)
+    }
+}
+
+/// `count * factor`, rounded up with a probability equal to its fraction.
+fn decayed<R: Rng>(count: u32, factor: f64, rng: &mut R) -> u32 {
+    let scaled = (count as f64 * factor).max(0.0).min(u32::MAX as f64);
+    let floor = scaled.floor();
+    if rng.gen::<f64>() < scaled - floor {
+        floor as u32 + 1
+    } else {
+        floor as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use testing::{
+        states,
+        Model,
+    };
+    use {
+        seeded_rng,
+        Cache,
+        MarkovGenerator,
+        Symbol,
+    };
+
+    /// Model of order 2 fed with `words`, interning `vocabulary` first so
+    /// that symbols match between models.
+    fn fed(words: &[&str], vocabulary: &[&str]) -> Model {
+        let mut markov = MarkovGenerator::with_order(HashMap::new(), 2);
+        for &word in vocabulary.iter() {
+            markov.symbols.intern(word);
+        }
+        markov.feed_from_words(words);
+        markov
+    }
+
+    fn symbols(markov: &Model, words: &[&str]) -> Vec<Symbol> {
+        words.iter().map(|&word| markov.symbols.get(word).unwrap()).collect()
+    }
+
+    #[test]
+    fn find_fed_skips_sentence_markers_only() {
+        let mut markov: Model = MarkovGenerator::with_order(HashMap::new(), 1);
+        markov.sentences = true;
+        markov.feed_from_words(&["a", "b."]);
+        markov.feed_from_words(&["c."]);
+        markov.feed_from_words(&["a", "b."]);
+        markov.end_document();
+        markov.feed_from_words(&["d."]);
+
+        // S a b. E S c. E S a b. E B S d. E
+        assert_eq!(markov.find_fed(symbols(&markov, &["a", "b."]).as_slice()), Some((8, 10)));
+        assert_eq!(markov.find_fed(symbols(&markov, &["b.", "c."]).as_slice()), Some((2, 6)));
+        assert_eq!(markov.find_fed(symbols(&markov, &["b.", "d."]).as_slice()), None);
+        assert_eq!(markov.find_fed(&[]), None);
+    }
+
+    #[test]
+    fn unfeeding_bridges_the_words_around() {
+        let all = ["a", "b", "c", "d", "e"];
+        for (index, &word) in all.iter().enumerate() {
+            let mut markov = fed(&all, &all);
+            assert!(markov.unfeed_from_words(&[word]));
+
+            let mut rest = all.to_vec();
+            rest.remove(index);
+            let expected = fed(rest.as_slice(), &all);
+            assert_eq!(markov.words(), expected.words());
+            assert_eq!(states(&markov), states(&expected));
+        }
+
+        let mut markov = fed(&all, &all);
+        assert!(!markov.unfeed_from_words(&["e", "a"]));
+        assert!(!markov.unfeed_from_words(&["unknown"]));
+    }
+
+    #[test]
+    fn unfeeding_after_trimming() {
+        let all = ["a", "b", "c", "d", "e", "f", "g", "h"];
+        let mut markov = fed(&all, &all);
+        assert_eq!(markov.trim_words(4), 4);
+        assert!(markov.unfeed_from_words(&["g"]));
+
+        let expected = fed(&["a", "b", "c", "d", "e", "f", "h"], &all);
+        assert_eq!(markov.words(), symbols(&markov, &["e", "f", "h"]).as_slice());
+        assert_eq!(states(&markov), states(&expected));
+    }
+
+    #[test]
+    fn repeated_small_decays_prune() {
+        let mut markov: Model = MarkovGenerator::with_order(HashMap::new(), 1);
+        markov.feed_from_words(&["a", "b", "c", "a", "b", "d"]);
+        let mut rng = seeded_rng(7);
+
+        // A count of 1 would round back to 1 after each of these.
+        for _ in range(0u, 100) {
+            markov.decay_with_rng(0.9, 1, &mut rng);
+        }
+        assert_eq!(markov.cache.states().count(), 0);
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+
+/// Compact identifier of an interned token.
+pub type Symbol = u32;
+
+/// Marker inserted before the first token of each sentence.
+pub const SENTENCE_START: Symbol = 0;
+/// Marker inserted after the last token of each sentence.
+pub const SENTENCE_END: Symbol = 1;
+/// Marker separating documents, see `MarkovGenerator::feed_document`.
+pub const DOCUMENT_BOUNDARY: Symbol = 2;
+
+/// Symbols below this one are markers rather than text.
+const MARKERS: Symbol = 3;
+
+/// Names the markers resolve to, e.g. in saved models.
+static MARKER_NAMES: [&'static str, ..3] = ["\u0002", "\u0003", "\u001c"];
+
+/// Two-way mapping between tokens and their symbols.
+#[deriving(Clone, Show)]
+pub struct Interner {
+    symbols: HashMap<String, Symbol>,
+    names: Vec<String>,
+}
+
+impl Interner {
+    /// Create an interner with the markers reserved. They are left out of
+    /// the lookup table, so that no corpus text ever maps to a marker.
+    pub fn new() -> Interner {
+        Interner {
+            symbols: HashMap::new(),
+            names: MARKER_NAMES.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    /// Whether `symbol` is a marker such as `SENTENCE_START` rather than text.
+    pub fn is_marker(symbol: Symbol) -> bool {
+        symbol < MARKERS
+    }
+
+    /// Return the marker whose name is `name`. Unlike `get`, this is meant
+    /// for formats naming the markers explicitly.
+    pub fn marker(name: &str) -> Option<Symbol> {
+        MARKER_NAMES.iter().position(|&marker| marker == name).map(|index| index as Symbol)
+    }
+
+    /// Return the symbol of `name`, allocating a new one if it was never seen.
+    /// Never returns a marker, even for a marker's name.
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        match self.symbols.get(name) {
+            Some(&symbol) => return symbol,
+            None => {}
+        }
+
+        let symbol = self.names.len() as Symbol;
+        self.symbols.insert(name.to_string(), symbol);
+        self.names.push(name.to_string());
+        symbol
+    }
+
+    /// Look up the symbol of `name` without interning it.
+    pub fn get(&self, name: &str) -> Option<Symbol> {
+        self.symbols.get(name).map(|&symbol| symbol)
+    }
+
+    pub fn resolve(&self, symbol: Symbol) -> &str {
+        self.names[symbol as uint].as_slice()
+    }
+
+    pub fn names(&self) -> &[String] {
+        self.names.as_slice()
+    }
+
+    pub fn len(&self) -> uint {
+        self.names.len()
+    }
+
+    /// FNV-1a hash of every name in symbol order, telling whether two
+    /// interners map tokens the same way.
+    pub fn fingerprint(&self) -> u32 {
+        let mut hash = 0x811c9dc5u32;
+        for name in self.names.iter() {
+            for &byte in name.as_bytes().iter().chain([0u8].iter()) {
+                hash = (hash ^ byte as u32) * 0x01000193;
+            }
+        }
+        hash
+    }
+}
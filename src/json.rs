@@ -0,0 +1,266 @@
+/*!
+ * JSON export and import of a trained model.
+ *
+ * The document has the following shape:
+ *
+ * ```text
+ * {
+ *     "version": 1,
+ *     "order": 2,
+ *     "min_order": 2,
+ *     "tokenizer": "whitespace",
+ *     "sentences": false,
+ *     "symbols": ["\u0002", "\u0003", "\u001c", "Hello", "world", ...],
+ *     "words": ["Hello", "world", ...],
+ *     "seeds": null,
+ *     "states": [
+ *         {
+ *             "prefix": ["Hello", "world"],
+ *             "successors": [{"token": "my", "count": 1}, ...]
+ *         },
+ *         ...
+ *     ]
+ * }
+ * ```
+ *
+ * `symbols` is the symbol table in symbol order, starting with the sentence
+ * start, sentence end and document boundary markers, and `words` is the
+ * seed data (every fed token, in order). `seeds` lists the positions in
+ * `words` where generation may start, `null` allowing any position.
+ * `states` lists each prefix with its successors, sorted by prefix, whose
+ * length ranges from `min_order` to `order`. `sentences` tells whether fed
+ * sentences are wrapped in markers.
+ *
+ * Markers are written as their names, and tokens whose text is a marker name
+ * or starts with `"\u001b"` are written with an extra `"\u001b"` in front.
+ *
+ * Tokens that are used in `words` or `states` but missing from `symbols`
+ * are interned after them, so the document can be edited by hand.
+ */
+
+use std::io::IoResult;
+use serialize::Encodable;
+use serialize::json;
+
+use {
+    tokenizer_by_name,
+    Cache,
+    CacheMut,
+    Interner,
+    MarkovGenerator,
+    ModelError,
+    Symbol,
+    Transition,
+    MAX_ORDER,
+};
+
+pub const VERSION: u32 = 1;
+
+/// Prefix of the tokens whose text could be mistaken for a marker name.
+const ESCAPE: char = '\u001b';
+
+#[deriving(Clone, PartialEq, Show, Encodable, Decodable)]
+pub struct JsonModel {
+    pub version: u32,
+    pub order: uint,
+    pub min_order: uint,
+    pub tokenizer: String,
+    pub sentences: bool,
+    pub symbols: Vec<String>,
+    pub words: Vec<String>,
+    pub seeds: Option<Vec<uint>>,
+    pub states: Vec<JsonState>,
+}
+
+#[deriving(Clone, PartialEq, Show, Encodable, Decodable)]
+pub struct JsonState {
+    pub prefix: Vec<String>,
+    pub successors: Vec<JsonSuccessor>,
+}
+
+#[deriving(Clone, PartialEq, Show, Encodable, Decodable)]
+pub struct JsonSuccessor {
+    pub token: String,
+    pub count: u32,
+}
+
+impl<C> MarkovGenerator<C> where C: Cache {
+    pub fn to_json_model(&self) -> JsonModel {
+        let resolve = |symbol: &Symbol| export_token(&self.symbols, *symbol);
+
+        let mut states: Vec<(&[Symbol], &[Transition])> = self.cache.states().collect();
+        states.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
+
+        JsonModel {
+            version: VERSION,
+            order: self.order,
+            min_order: self.min_order,
+            tokenizer: self.tokenizer.name().to_string(),
+            sentences: self.sentences,
+            symbols: range(0, self.symbols.len()).map(|symbol| resolve(&(symbol as Symbol))).collect(),
+            words: self.words.iter().map(|symbol| resolve(symbol)).collect(),
+            seeds: self.seeds.clone(),
+            states: states.iter().map(|&(key, transitions)| JsonState {
+                prefix: key.iter().map(|symbol| resolve(symbol)).collect(),
+                successors: transitions.iter().map(|transition| JsonSuccessor {
+                    token: resolve(&transition.symbol),
+                    count: transition.count,
+                }).collect(),
+            }).collect(),
+        }
+    }
+
+    /// Write the model as a pretty-printed JSON document.
+    pub fn export_json(&self, writer: &mut Writer) -> IoResult<()> {
+        let model = self.to_json_model();
+        let mut encoder = json::PrettyEncoder::new(writer);
+        model.encode(&mut encoder)
+    }
+}
+
+impl<C> MarkovGenerator<C> where C: CacheMut {
+    pub fn from_json_model(model: &JsonModel, cache: C) -> Result<MarkovGenerator<C>, ModelError> {
+        if model.version != VERSION {
+            return Err(ModelError::UnsupportedVersion(model.version as u16));
+        }
+        if model.order > MAX_ORDER {
+            return Err(ModelError::Corrupted("order too high".to_string()));
+        }
+        if model.min_order > model.order {
+            return Err(ModelError::Corrupted("minimum order above the order".to_string()));
+        }
+
+        let tokenizer = match tokenizer_by_name(model.tokenizer.as_slice()) {
+            Some(tokenizer) => tokenizer,
+            None => {
+                let detail = format!("unknown tokenizer `{}`", model.tokenizer);
+                return Err(ModelError::Corrupted(detail));
+            }
+        };
+
+        let mut markov = MarkovGenerator::with_order(cache, model.order);
+        markov.tokenizer = tokenizer;
+        markov.sentences = model.sentences;
+        markov.min_order = model.min_order;
+
+        for name in model.symbols.iter() {
+            import_token(&mut markov.symbols, name.as_slice());
+        }
+        for word in model.words.iter() {
+            let symbol = import_token(&mut markov.symbols, word.as_slice());
+            markov.words.push(symbol);
+        }
+        match model.seeds {
+            Some(ref seeds) if seeds.iter().any(|&seed| seed + model.order > model.words.len()) => {
+                return Err(ModelError::Corrupted("seed out of range".to_string()));
+            }
+            _ => markov.seeds = model.seeds.clone(),
+        }
+        for state in model.states.iter() {
+            if state.prefix.len() < model.min_order || state.prefix.len() > model.order {
+                return Err(ModelError::Corrupted("prefix length out of the stored orders".to_string()));
+            }
+            let key: Vec<Symbol> = state.prefix
+                                        .iter()
+                                        .map(|word| import_token(&mut markov.symbols, word.as_slice()))
+                                        .collect();
+            for successor in state.successors.iter() {
+                let symbol = import_token(&mut markov.symbols, successor.token.as_slice());
+                markov.cache.add(key.as_slice(), symbol, successor.count);
+            }
+        }
+
+        Ok(markov)
+    }
+
+    /// Rebuild a model from a JSON document written by `export_json`.
+    pub fn import_json(reader: &mut Reader, cache: C) -> Result<MarkovGenerator<C>, ModelError> {
+        let text = try!(reader.read_to_string());
+        let model: JsonModel = match json::decode(text.as_slice()) {
+            Ok(model) => model,
+            Err(err) => return Err(ModelError::Corrupted(format!("invalid JSON model: {}", err))),
+        };
+
+        MarkovGenerator::from_json_model(&model, cache)
+    }
+}
+
+fn export_token(symbols: &Interner, symbol: Symbol) -> String {
+    let name = symbols.resolve(symbol);
+    if !Interner::is_marker(symbol) && (Interner::marker(name).is_some() || name.starts_with("\u001b")) {
+        format!("{}{}", ESCAPE, name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn import_token(symbols: &mut Interner, token: &str) -> Symbol {
+    if token.starts_with("\u001b") {
+        return symbols.intern(token[ESCAPE.len_utf8()..]);
+    }
+
+    match Interner::marker(token) {
+        Some(marker) => marker,
+        None => symbols.intern(token),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{
+        MemReader,
+        MemWriter,
+    };
+
+    use testing::{
+        assert_same,
+        fixture,
+        Model,
+    };
+    use {
+        MarkovGenerator,
+        ModelError,
+    };
+
+    fn round_trip(markov: &Model) -> Model {
+        let mut writer = MemWriter::new();
+        markov.export_json(&mut writer).unwrap();
+        let mut reader = MemReader::new(writer.unwrap());
+        match MarkovGenerator::import_json(&mut reader, HashMap::new()) {
+            Ok(markov) => markov,
+            Err(err) => panic!("import failed: {}", err),
+        }
+    }
+
+    #[test]
+    fn round_trip_keeps_the_model() {
+        let markov = fixture();
+        assert_same(&markov, &round_trip(&markov));
+    }
+
+    #[test]
+    fn round_trip_keeps_tokens_looking_like_markers() {
+        let mut markov = MarkovGenerator::new(HashMap::new());
+        markov.feed_document(&["\u0002", "\u001bescaped", "\u001c", "plain"]);
+
+        let imported = round_trip(&markov);
+        assert_same(&markov, &imported);
+        assert_eq!(imported.symbols.get("\u0002"), markov.symbols.get("\u0002"));
+        assert!(imported.symbols.get("\u0002").is_some());
+    }
+
+    #[test]
+    fn orders_are_checked() {
+        let mut model = fixture().to_json_model();
+        model.order = 1 << 31;
+        model.min_order = 0;
+        let markov: Result<Model, ModelError> = MarkovGenerator::from_json_model(&model, HashMap::new());
+        assert!(markov.is_err());
+
+        let mut model = fixture().to_json_model();
+        model.states.as_mut_slice()[0].prefix.push_all(&["a".to_string(), "b".to_string(), "c".to_string()]);
+        let markov: Result<Model, ModelError> = MarkovGenerator::from_json_model(&model, HashMap::new());
+        assert!(markov.is_err());
+    }
+}
@@ -11,154 +11,805 @@
  * let mut markov = MarkovGenerator::new(HashMap::new());
  * markov.feed_from_words(&["Hello", "world", "my", "name", "is", "KokaKiwi"]);
  * let text = markov.generate_text(15);
+ *
+ * // Same seed, same text.
+ * let seeded = markov.generate_text_with_rng(15, &mut markov::seeded_rng(42));
+ * assert_eq!(seeded, markov.generate_text_with_rng(15, &mut markov::seeded_rng(42)));
  * ```
  */
 
+#[cfg(feature = "bzip2")]
+extern crate bzip2;
+#[cfg(feature = "gzip")]
+extern crate flate2;
 #[phase(plugin, link)]
 extern crate log;
+extern crate libc;
+extern crate serialize;
 
-use std::collections::HashMap;
-use std::io::{
-    BufferedReader,
-    File,
+pub use compress::Compression;
+pub use corpus::corpus_files;
+pub use detokenizer::{
+    Concat,
+    Detokenizer,
+    Prose,
+    Spaced,
+};
+pub use feed::{
+    CorpusError,
+    FeedError,
+    FeedErrorKind,
+    FeedSummary,
+    InvalidInput,
+};
+pub use interner::{
+    Interner,
+    Symbol,
+    DOCUMENT_BOUNDARY,
+    SENTENCE_END,
+    SENTENCE_START,
+};
+pub use json::{
+    JsonModel,
+    JsonState,
+    JsonSuccessor,
+};
+pub use mapped::MappedCache;
+pub use merge::Mixture;
+pub use model::{
+    ModelError,
+    MAX_ORDER,
 };
+pub use prompt::{
+    GenerateError,
+    PrefixFallback,
+};
+pub use overlap::{
+    OverlapGuard,
+    OverlapStats,
+};
+pub use prune::PruneStats;
+pub use records::RecordFormat;
+pub use sampling::Sampling;
+pub use score::{
+    Score,
+    Scorer,
+    Smoothing,
+};
+pub use tokenizer::{
+    tokenizer_by_name,
+    Characters,
+    Graphemes,
+    Tokenizer,
+    Whitespace,
+    WordPunct,
+};
+
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::HashMap;
+use std::collections::hash_map;
+use std::default::Default;
+use std::mem;
 use std::rand::{
     task_rng,
     Rng,
+    SeedableRng,
+    XorShiftRng,
 };
+use std::rc::Rc;
+
+use overlap::{
+    Guard,
+    Positions,
+};
+
+mod compress;
+mod corpus;
+mod detokenizer;
+mod feed;
+mod forget;
+mod interner;
+mod json;
+mod mapped;
+mod merge;
+mod model;
+mod overlap;
+mod prompt;
+mod prune;
+mod records;
+mod sampling;
+mod score;
+#[cfg(test)]
+mod testing;
+mod tokenizer;
+
+/// A successor of a state, along with the number of times it has been seen.
+#[deriving(Clone, PartialEq, Show)]
+#[repr(C)]
+pub struct Transition {
+    pub symbol: Symbol,
+    pub count: u32,
+}
 
 pub trait Cache {
-    fn put(&mut self, key: (&str, &str), value: &str);
-    fn get(&self, key: (&str, &str)) -> Option<&[String]>;
+    fn get(&self, key: &[Symbol]) -> Option<&[Transition]>;
+    /// Iterate over every known state along with its successors.
+    fn states<'a>(&'a self) -> Box<Iterator<(&'a [Symbol], &'a [Transition])> + 'a>;
 
-    fn has(&self, key: (&str, &str)) -> bool {
+    fn has(&self, key: &[Symbol]) -> bool {
         self.get(key).is_some()
     }
+
+    /// `Interner::fingerprint` and number of the symbols the cache was built
+    /// with, for caches that record them.
+    fn fingerprint(&self) -> Option<(u32, uint)> {
+        None
+    }
 }
 
-impl Cache for HashMap<(String, String), Vec<String>> {
-    fn put(&mut self, (w1, w2): (&str, &str), value: &str) {
-        let w1 = w1.to_string();
-        let w2 = w2.to_string();
-        let value = value.to_string();
+/// A cache that can be trained.
+pub trait CacheMut: Cache {
+    /// Record `count` more occurrences of `value` following `key`.
+    fn add(&mut self, key: &[Symbol], value: Symbol, count: u32);
 
-        if self.has((w1.as_slice(), w2.as_slice())) {
-            self[(w1, w2)].push(value);
-        } else {
-            self.insert((w1, w2), vec![value]);
+    /// Forget up to `count` occurrences of `value` following `key`, dropping
+    /// the transition (and the state) once nothing is left.
+    fn remove(&mut self, key: &[Symbol], value: Symbol, count: u32);
+    /// Visit every transition, keeping only those for which `f` returns true.
+    /// States left without any transition are dropped.
+    fn retain(&mut self, f: |&[Symbol], &mut Transition| -> bool);
+
+    fn put(&mut self, key: &[Symbol], value: Symbol) {
+        self.add(key, value, 1)
+    }
+}
+
+impl Cache for HashMap<Vec<Symbol>, Vec<Transition>> {
+    fn get(&self, key: &[Symbol]) -> Option<&[Transition]> {
+        self.get(key).map(|transitions| transitions.as_slice())
+    }
+
+    fn states<'a>(&'a self) -> Box<Iterator<(&'a [Symbol], &'a [Transition])> + 'a> {
+        box HashMapStates {
+            iter: self.iter(),
+        }
+    }
+}
+
+impl CacheMut for HashMap<Vec<Symbol>, Vec<Transition>> {
+    fn add(&mut self, key: &[Symbol], value: Symbol, count: u32) {
+        match self.get_mut(key) {
+            Some(transitions) => {
+                match transitions.iter_mut().find(|transition| transition.symbol == value) {
+                    Some(transition) => transition.count += count,
+                    None => transitions.push(Transition {
+                        symbol: value,
+                        count: count,
+                    }),
+                }
+                return;
+            }
+            None => {}
+        }
+
+        self.insert(key.to_vec(), vec![Transition {
+            symbol: value,
+            count: count,
+        }]);
+    }
+
+    fn remove(&mut self, key: &[Symbol], value: Symbol, count: u32) {
+        let empty = match self.get_mut(key) {
+            Some(transitions) => {
+                match transitions.iter().position(|transition| transition.symbol == value) {
+                    Some(index) if transitions[index].count > count => transitions[index].count -= count,
+                    Some(index) => {
+                        transitions.remove(index);
+                    }
+                    None => {}
+                }
+                transitions.is_empty()
+            }
+            None => false,
+        };
+
+        if empty {
+            self.remove(key);
+        }
+    }
+
+    fn retain(&mut self, f: |&[Symbol], &mut Transition| -> bool) {
+        let mut empty = Vec::new();
+
+        for (key, transitions) in self.iter_mut() {
+            let old = mem::replace(transitions, Vec::new());
+            for mut transition in old.into_iter() {
+                if f(key.as_slice(), &mut transition) {
+                    transitions.push(transition);
+                }
+            }
+
+            if transitions.is_empty() {
+                empty.push(key.clone());
+            }
+        }
+
+        for key in empty.iter() {
+            self.remove(key);
+        }
+    }
+}
+
+struct HashMapStates<'a> {
+    iter: hash_map::Entries<'a, Vec<Symbol>, Vec<Transition>>,
+}
+
+impl<'a> Iterator<(&'a [Symbol], &'a [Transition])> for HashMapStates<'a> {
+    fn next(&mut self) -> Option<(&'a [Symbol], &'a [Transition])> {
+        match self.iter.next() {
+            Some((key, transitions)) => Some((key.as_slice(), transitions.as_slice())),
+            None => None,
         }
     }
+}
 
-    fn get(&self, (w1, w2): (&str, &str)) -> Option<&[String]> {
-        let w1 = w1.to_string();
-        let w2 = w2.to_string();
+/// Whether `word` ends a sentence, ignoring trailing quotes and brackets.
+fn ends_sentence(word: &str) -> bool {
+    let word = word.trim_right_chars(['"', '\'', ')', ']', '»', '”', '’'].as_slice());
 
-        self.get(&(w1, w2)).map(|words| words.as_slice())
+    match word.chars().next_back() {
+        Some(c) => ['.', '!', '?', '…'].contains(&c),
+        None => false,
     }
 }
 
+/// Create a random number generator from a numeric seed.
+///
+/// Generating with the same model, seed and parameters always produces the
+/// same text, whatever the platform.
+pub fn seeded_rng(seed: u64) -> XorShiftRng {
+    // XorShift must not be seeded with zeros only, hence the constant words.
+    SeedableRng::from_seed([seed as u32, (seed >> 32) as u32, 0x9e3779b9, 0x7f4a7c15])
+}
+
+/// Whether a document ends within `state`, i.e. a boundary follows text.
+fn ends_document(state: &[Symbol]) -> bool {
+    state.iter().skip_while(|&&symbol| symbol == DOCUMENT_BOUNDARY).any(|&symbol| symbol == DOCUMENT_BOUNDARY)
+}
+
+/// Number of symbols of `symbols` standing for text rather than markers.
+fn text_len(symbols: &[Symbol]) -> uint {
+    symbols.iter().filter(|&&symbol| !Interner::is_marker(symbol)).count()
+}
+
+/// Keep the shortest prefix of `symbols` holding `size` text symbols.
+fn truncate_text(symbols: &mut Vec<Symbol>, size: uint) {
+    let mut len = 0;
+    match symbols.iter().position(|&symbol| {
+        if !Interner::is_marker(symbol) {
+            len += 1;
+        }
+        len > size
+    }) {
+        Some(end) => symbols.truncate(end),
+        None => {}
+    }
+}
+
+/// Pick an index below `len`, drawing the same numbers on 32 and 64-bit targets.
+fn gen_index<R: Rng>(rng: &mut R, len: uint) -> uint {
+    rng.gen_range(0u64, len as u64) as uint
+}
+
+/// Pick a transition with a probability proportional to its count.
+fn choose_weighted<'a, R: Rng>(rng: &mut R, transitions: &'a [Transition]) -> Option<&'a Transition> {
+    let total = transitions.iter().fold(0u64, |total, transition| total + transition.count as u64);
+    if total == 0 {
+        return None;
+    }
+
+    let mut target = rng.gen_range(0, total);
+    for transition in transitions.iter() {
+        let count = transition.count as u64;
+        if target < count {
+            return Some(transition);
+        }
+        target -= count;
+    }
+
+    None
+}
+
 pub struct MarkovGenerator<C: Cache> {
     pub cache: C,
-    pub words: Vec<String>,
+    pub order: uint,
+    pub symbols: Interner,
+    /// Every fed symbol in order, see `words()`.
+    words: Vec<Symbol>,
+    /// Tokenizer used by every feeding path, recorded in saved models.
+    pub tokenizer: Box<Tokenizer + 'static>,
+    /// Overrides the tokenizer's own detokenizer when set.
+    pub detokenizer: Option<Box<Detokenizer + 'static>>,
+    /// Wrap fed sentences in `SENTENCE_START` and `SENTENCE_END` markers.
+    pub sentences: bool,
+    /// Reverse-direction chain, trained alongside `cache` when set. Its states
+    /// are the `order` following words, closest first.
+    pub backward: Option<C>,
+    /// How successors are picked by every generation method.
+    pub sampling: Sampling,
+    /// Lowest order whose transitions are stored, states of every order
+    /// from `min_order` to `order` being kept in the same cache.
+    pub min_order: uint,
+    /// How generation falls back to lower orders.
+    pub backoff: Backoff,
+    /// Positions of `words` where `generate_text` may start, any position
+    /// being allowed when unset. Set by `compact` and `decay` so that
+    /// generation never starts from a removed state.
+    pub seeds: Option<Vec<uint>>,
+    /// Limits how much of the corpus `generate_text` may copy verbatim.
+    pub overlap_guard: Option<OverlapGuard>,
+    /// Index of `words` for the overlap statistics, built on first use.
+    positions: RefCell<Option<Rc<Positions>>>,
+}
+
+/// Strategy used to fall back to lower orders during generation, for models
+/// trained with `min_order` below `order`.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Backoff {
+    /// Only use states of the highest order, stopping at dead ends.
+    Off,
+    /// Use the highest order whose state is known (stupid backoff).
+    Stupid,
+    /// Like `Stupid`, but also back off from known states with a probability
+    /// given by absolute discounting: `discount` times the number of distinct
+    /// successors over the total count, as in Katz backoff.
+    Katz(f64),
 }
 
 impl<C> MarkovGenerator<C> where C: Cache {
     pub fn new(cache: C) -> MarkovGenerator<C> {
+        MarkovGenerator::with_order(cache, 2)
+    }
+
+    /// Create a generator whose states are made of the `order` preceding words.
+    pub fn with_order(cache: C, order: uint) -> MarkovGenerator<C> {
         MarkovGenerator {
             cache: cache,
+            order: order,
+            symbols: Interner::new(),
             words: Vec::new(),
+            tokenizer: box Whitespace,
+            detokenizer: None,
+            sentences: false,
+            backward: None,
+            sampling: Default::default(),
+            min_order: order,
+            backoff: Backoff::Off,
+            seeds: None,
+            overlap_guard: None,
+            positions: RefCell::new(None),
         }
     }
 
-    pub fn feed_from_words(&mut self, words: &[&str]) {
-        {
-            let last_words: Vec<&str> = if self.words.len() > 3 {
-                self.words[self.words.len() - 3..]
-                        .iter()
-                        .map(|word| word.as_slice())
-                        .collect()
-            } else {
-                Vec::new()
+    /// Every fed symbol in order, markers included. Only the feeding methods
+    /// change them, keeping the indexes built from them up to date.
+    pub fn words(&self) -> &[Symbol] {
+        self.words.as_slice()
+    }
+
+    /// Create a generator storing every order from 0 to `order`, backing off
+    /// to lower orders instead of stopping at dead ends.
+    pub fn with_backoff(cache: C, order: uint) -> MarkovGenerator<C> {
+        let mut markov = MarkovGenerator::with_order(cache, order);
+        markov.min_order = 0;
+        markov.backoff = Backoff::Stupid;
+        markov
+    }
+
+    pub fn generate_text(&self, size: uint) -> String {
+        self.generate_text_with_rng(size, &mut task_rng())
+    }
+
+    pub fn generate_text_with_rng<R: Rng>(&self, size: uint, rng: &mut R) -> String {
+        match self.overlap_guard {
+            Some(_) => {
+                let (text, _) = self.generate_text_with_stats(size, rng);
+                text
+            }
+            None => self.detokenize(self.generate_symbols(size, rng, None).as_slice()),
+        }
+    }
+
+    /// Generate symbols holding up to `size` words from a random seed, up to
+    /// the end of the document, rejecting successors that would copy more
+    /// than the guard allows from the corpus.
+    fn generate_symbols<R: Rng>(&self, size: uint, rng: &mut R, guard: Option<&Guard>) -> Vec<Symbol> {
+        let seed = match self.seeds {
+            Some(ref seeds) if seeds.is_empty() => return Vec::new(),
+            Some(ref seeds) => seeds[gen_index(rng, seeds.len())],
+            None if self.words.len() <= self.order => return Vec::new(),
+            None => gen_index(rng, self.words.len() - self.order),
+        };
+        let seed = self.skip_document_end(seed);
+        let mut symbols = self.words[seed..seed + self.order].to_vec();
+        truncate_text(&mut symbols, size);
+        let mut len = text_len(symbols.as_slice());
+
+        let mut rejected = Vec::new();
+        while len < size {
+            let next = match self.next_symbol_excluding(&self.cache, symbols.as_slice(), rejected.as_slice(), rng) {
+                Some(DOCUMENT_BOUNDARY) | None => break, // Break loop, we got no more words to put in the text.
+                Some(symbol) => symbol,
             };
-            let mut triples = Triples::new(last_words.iter().chain(words.iter()));
 
-            for (&w1, &w2, &w3) in triples {
-                self.cache.put((w1, w2), w3);
+            match guard {
+                Some(guard) if !guard.allows(symbols.as_slice(), next) => {
+                    rejected.push(next);
+                    continue;
+                }
+                _ => {}
             }
+
+            rejected.clear();
+            symbols.push(next);
+            if !Interner::is_marker(next) {
+                len += 1;
+            }
+        }
+
+        symbols
+    }
+
+    /// Move `seed` past the end of the document its state straddles, to the
+    /// start of the next one, since generation would stop there at once.
+    fn skip_document_end(&self, mut seed: uint) -> uint {
+        while seed + self.order < self.words.len() && ends_document(self.words[seed..seed + self.order]) {
+            seed += 1;
         }
+        seed
+    }
 
-        self.words.extend(words.iter().map(|s| s.to_string()));
+    /// Generate a whole sentence, from a sentence start up to a sentence end
+    /// or `max_size` words. The model must have been fed with `sentences` set.
+    pub fn generate_sentence(&self, max_size: uint) -> String {
+        self.generate_sentence_with_rng(max_size, &mut task_rng())
     }
 
-    pub fn feed_from_file(&mut self, path: &Path) {
-        let mut reader = BufferedReader::new(File::open(path));
+    pub fn generate_sentence_with_rng<R: Rng>(&self, max_size: uint, rng: &mut R) -> String {
+        let mut symbols = Vec::from_elem(cmp::max(self.order, 1), SENTENCE_START);
+        let start = symbols.len();
+        let mut len = 0;
+
+        while len < max_size {
+            match self.next_symbol(&self.cache, symbols.as_slice(), rng) {
+                Some(SENTENCE_END) | Some(DOCUMENT_BOUNDARY) | None => break,
+                Some(symbol) => {
+                    symbols.push(symbol);
+                    if !Interner::is_marker(symbol) {
+                        len += 1;
+                    }
+                }
+            }
+        }
 
-        for line in reader.lines() {
-            let line = line.unwrap();
+        self.detokenize(symbols[start..])
+    }
 
-            let seps = |c: char| [' ', '\t', '\n', '\r'].contains(&c);
-            let filter = |word: &str| !word.is_empty();
-            let words: Vec<&str> = line
-                                    .split(seps)
-                                    .filter(|word| filter(*word))
+    /// Pick a successor of the state made of the last `order` symbols,
+    /// backing off to lower orders as configured.
+    fn next_symbol<R: Rng>(&self, cache: &C, symbols: &[Symbol], rng: &mut R) -> Option<Symbol> {
+        self.next_symbol_excluding(cache, symbols, &[], rng)
+    }
+
+    /// Like `next_symbol`, but never picks one of `excluded`.
+    fn next_symbol_excluding<R: Rng>(&self, cache: &C, symbols: &[Symbol], excluded: &[Symbol], rng: &mut R)
+                                     -> Option<Symbol> {
+        let lowest = match self.backoff {
+            Backoff::Off => self.order,
+            _ => cmp::min(self.min_order, self.order),
+        };
+
+        for order in range(lowest, self.order + 1).rev() {
+            let successors = match cache.get(symbols[symbols.len() - order..]) {
+                Some(successors) => successors,
+                None => continue,
+            };
+
+            let allowed: Vec<Transition>;
+            let successors = if excluded.is_empty() {
+                successors
+            } else {
+                allowed = successors.iter()
+                                    .filter(|transition| !excluded.contains(&transition.symbol))
+                                    .map(|transition| transition.clone())
                                     .collect();
-            debug!("Collected words: {}", words);
+                if allowed.is_empty() {
+                    continue;
+                }
+                allowed.as_slice()
+            };
 
-            self.feed_from_words(words.as_slice());
+            match self.backoff {
+                Backoff::Katz(discount) if order > lowest => {
+                    let total = successors.iter().fold(0u64, |total, transition| total + transition.count as u64);
+                    let reserved = discount * successors.len() as f64 / total as f64;
+                    if rng.gen::<f64>() < reserved {
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+
+            return self.sampling.choose(rng, successors).map(|transition| transition.symbol);
         }
+
+        None
     }
 
-    pub fn generate_text(&self, size: uint) -> String {
-        let mut words: Vec<&str> = Vec::new();
-        let mut rng = task_rng();
-
-        let seed = rng.gen_range(0, self.words.len() - 3);
-        let mut w1 = &self.words[seed];
-        let mut w2 = &self.words[seed + 1];
-
-        for _ in range(0, size) {
-            words.push(w1.as_slice());
-
-            let old_w1 = w1;
-            w1 = w2;
-            w2 = {
-                let words = match self.cache.get((old_w1.as_slice(), w2.as_slice())) {
-                    Some(words) => words,
-                    None => break, // Break loop, we got no more words to put in the text.
-                };
-                rng.choose(words).unwrap()
+    /// Turn symbols back into text with the generator's detokenizer,
+    /// leaving markers out.
+    pub fn detokenize(&self, symbols: &[Symbol]) -> String {
+        let words = self.resolve_text(symbols);
+        self.detokenize_words(words.as_slice())
+    }
+
+    /// Resolve the symbols that stand for text, leaving markers out.
+    fn resolve_text(&self, symbols: &[Symbol]) -> Vec<&str> {
+        symbols.iter()
+               .filter(|&&symbol| !Interner::is_marker(symbol))
+               .map(|&symbol| self.symbols.resolve(symbol))
+               .collect()
+    }
+
+    fn detokenize_words(&self, words: &[&str]) -> String {
+        match self.detokenizer {
+            Some(ref detokenizer) => detokenizer.detokenize(words),
+            None => self.tokenizer.detokenizer().detokenize(words),
+        }
+    }
+}
+
+impl<C> MarkovGenerator<C> where C: CacheMut {
+    pub fn feed_from_words(&mut self, words: &[&str]) {
+        let mut symbols = Vec::with_capacity(words.len());
+
+        if self.sentences {
+            let mut in_sentence = match self.words.last() {
+                Some(&symbol) => symbol != SENTENCE_END && symbol != DOCUMENT_BOUNDARY,
+                None => false,
             };
+
+            for &word in words.iter() {
+                if !in_sentence {
+                    symbols.grow(cmp::max(self.order, 1), SENTENCE_START);
+                    in_sentence = true;
+                }
+                symbols.push(self.symbols.intern(word));
+                if ends_sentence(word) {
+                    symbols.push(SENTENCE_END);
+                    in_sentence = false;
+                }
+            }
+        } else {
+            symbols.extend(words.iter().map(|word| self.symbols.intern(*word)));
         }
 
-        words.connect(" ")
+        self.feed_from_symbols(symbols);
+    }
+
+    fn feed_from_symbols(&mut self, symbols: Vec<Symbol>) {
+        let start = self.words.len();
+        self.words.extend(symbols.into_iter());
+        self.invalidate_positions();
+
+        for end in range(cmp::max(start, self.order), self.words.len()) {
+            let window = self.words[end - self.order..end + 1].to_vec();
+            self.record_window(window.as_slice(), false);
+
+            match self.seeds {
+                Some(ref mut seeds) => seeds.push(end - self.order),
+                None => {}
+            }
+        }
+    }
+
+    /// Drop the index of the fed words, which must be done whenever they
+    /// change.
+    fn invalidate_positions(&mut self) {
+        *self.positions.borrow_mut() = None;
+    }
+
+    /// Count (or uncount, when `forget` is set) the transitions of a window of
+    /// `order + 1` symbols, in every stored order and in the backward chain.
+    fn record_window(&mut self, window: &[Symbol], forget: bool) {
+        let key = window.init();
+        let value = *window.last().unwrap();
+
+        for order in range(cmp::min(self.min_order, self.order), self.order + 1) {
+            if forget {
+                self.cache.remove(key[self.order - order..], value, 1);
+            } else {
+                self.cache.put(key[self.order - order..], value);
+            }
+        }
+
+        match self.backward {
+            Some(ref mut backward) => {
+                let key: Vec<Symbol> = window.tail().iter().rev().map(|&symbol| symbol).collect();
+                if forget {
+                    backward.remove(key.as_slice(), window[0], 1);
+                } else {
+                    backward.put(key.as_slice(), window[0]);
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Train `cache` as the backward chain from the words fed so far, e.g.
+    /// after loading a saved model.
+    pub fn rebuild_backward(&mut self, mut cache: C) {
+        for ngram in NGrams::new(self.words.iter(), self.order + 1) {
+            let key: Vec<Symbol> = ngram.tail().iter().rev().map(|&&symbol| symbol).collect();
+            cache.put(key.as_slice(), **ngram.head().unwrap());
+        }
+
+        self.backward = Some(cache);
+    }
+
+    /// Tokenize `text` with the generator's tokenizer and feed the tokens.
+    pub fn feed_from_str(&mut self, text: &str) {
+        let words = self.tokenizer.tokenize(text);
+        debug!("Collected words: {}", words);
+
+        self.feed_from_words(words.as_slice());
+    }
+
+    /// Feed `words` as a whole document: unlike `feed_from_words`, no
+    /// transition links them to the words fed before or after.
+    pub fn feed_document(&mut self, words: &[&str]) {
+        self.end_document();
+        self.feed_from_words(words);
+        self.end_document();
+    }
+
+    /// Tokenize `text` with the generator's tokenizer and feed the tokens as
+    /// a whole document.
+    pub fn feed_document_from_str(&mut self, text: &str) {
+        let words = self.tokenizer.tokenize(text);
+        self.feed_document(words.as_slice());
+    }
+
+    /// End the current document with `order` (at least one)
+    /// `DOCUMENT_BOUNDARY` markers, closing its last sentence if needed, so
+    /// that the next fed words start a new document. Does nothing right after
+    /// another boundary.
+    pub fn end_document(&mut self) {
+        let mut symbols = Vec::new();
+        match self.words.last() {
+            Some(&DOCUMENT_BOUNDARY) => return,
+            Some(&symbol) if self.sentences && !Interner::is_marker(symbol) => symbols.push(SENTENCE_END),
+            _ => {}
+        }
+
+        symbols.grow(cmp::max(self.order, 1), DOCUMENT_BOUNDARY);
+        self.feed_from_symbols(symbols);
     }
 }
 
-struct Triples<'a, T, I>
+/// Iterator over every window of `size` consecutive items.
+struct NGrams<'a, T, I>
     where I: Iterator<&'a T> + Clone {
     iter: I,
+    size: uint,
 }
 
-impl<'a, T, I> Triples<'a T, I>
+impl<'a, T, I> NGrams<'a, T, I>
     where I: Iterator<&'a T> + Clone {
-    pub fn new(iter: I) -> Triples<'a, T, I> {
-        Triples {
+    pub fn new(iter: I, size: uint) -> NGrams<'a, T, I> {
+        NGrams {
             iter: iter,
+            size: size,
         }
     }
 }
 
-impl<'a, T, I> Iterator<(&'a T, &'a T, &'a T)> for Triples<'a T, I>
+impl<'a, T, I> Iterator<Vec<&'a T>> for NGrams<'a, T, I>
     where I: Iterator<&'a T> + Clone {
-    fn next(&mut self) -> Option<(&'a T, &'a T, &'a T)> {
-        let a = self.iter.next();
-        let mut iter = self.iter.clone();
-        let b = iter.next();
-        let c = iter.next();
+    fn next(&mut self) -> Option<Vec<&'a T>> {
+        let ngram: Vec<&'a T> = self.iter.clone().take(self.size).collect();
+        self.iter.next();
 
-        match (a, b, c) {
-            (Some(a), Some(b), Some(c)) => Some((a, b, c)),
-            _ => None,
+        if ngram.len() == self.size {
+            Some(ngram)
+        } else {
+            None
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use testing::Model;
+    use {
+        seeded_rng,
+        Backoff,
+        CacheMut,
+        MarkovGenerator,
+        Symbol,
+    };
+
+    /// Model of order 2 knowing `x y -> z`, `y -> w` and `-> v`.
+    fn backoff_model(backoff: Backoff) -> Model {
+        let mut markov: Model = MarkovGenerator::with_backoff(HashMap::new(), 2);
+        markov.backoff = backoff;
+        for &word in ["q", "v", "w", "x", "y", "z"].iter() {
+            markov.symbols.intern(word);
+        }
+        let s = symbols(&markov, &["v", "w", "x", "y", "z"]);
+        markov.cache.add(&[s[2], s[3]], s[4], 1);
+        markov.cache.add(&[s[3]], s[1], 1);
+        markov.cache.add(&[], s[0], 1);
+        markov
+    }
+
+    fn symbols(markov: &Model, words: &[&str]) -> Vec<Symbol> {
+        words.iter().map(|&word| markov.symbols.get(word).unwrap()).collect()
+    }
+
+    /// The distinct words picked in `draws` tries after `context`, sorted,
+    /// with `None` for the tries that found nothing.
+    fn picked(markov: &Model, context: &[&str], excluded: &[&str], draws: uint) -> Vec<Option<String>> {
+        let context = symbols(markov, context);
+        let excluded = symbols(markov, excluded);
+        let mut rng = seeded_rng(3);
+        let mut picked: Vec<Option<String>> = range(0, draws).map(|_| {
+            markov.next_symbol_excluding(&markov.cache, context.as_slice(), excluded.as_slice(), &mut rng)
+                  .map(|symbol| markov.symbols.resolve(symbol).to_string())
+        }).collect();
+        picked.sort();
+        picked.dedup();
+        picked
+    }
+
+    fn words(words: &[&str]) -> Vec<Option<String>> {
+        words.iter().map(|&word| Some(word.to_string())).collect()
+    }
+
+    #[test]
+    fn stupid_backoff_uses_the_highest_known_order() {
+        let markov = backoff_model(Backoff::Stupid);
+        assert_eq!(picked(&markov, &["x", "y"], &[], 50), words(&["z"]));
+        assert_eq!(picked(&markov, &["x", "y"], &["z"], 50), words(&["w"]));
+        assert_eq!(picked(&markov, &["x", "y"], &["z", "w"], 50), words(&["v"]));
+        assert_eq!(picked(&markov, &["x", "y"], &["z", "w", "v"], 50), vec![None]);
+    }
+
+    #[test]
+    fn dead_ends_fall_back_to_lower_orders() {
+        let markov = backoff_model(Backoff::Stupid);
+        assert_eq!(picked(&markov, &["q", "y"], &[], 50), words(&["w"]));
+        assert_eq!(picked(&markov, &["y", "q"], &[], 50), words(&["v"]));
+
+        let markov = backoff_model(Backoff::Off);
+        assert_eq!(picked(&markov, &["q", "y"], &[], 50), vec![None]);
+        assert_eq!(picked(&markov, &["x", "y"], &["z"], 50), vec![None]);
+    }
+
+    #[test]
+    fn katz_backoff_reserves_the_discount() {
+        let markov = backoff_model(Backoff::Katz(0.0));
+        assert_eq!(picked(&markov, &["x", "y"], &[], 50), words(&["z"]));
+
+        // Every state has a single successor seen once, so a discount of 1
+        // always backs off down to the lowest order.
+        let markov = backoff_model(Backoff::Katz(1.0));
+        assert_eq!(picked(&markov, &["x", "y"], &[], 50), words(&["v"]));
+
+        let markov = backoff_model(Backoff::Katz(0.5));
+        assert_eq!(picked(&markov, &["x", "y"], &[], 200), words(&["v", "w", "z"]));
+    }
+}
@@ -0,0 +1,407 @@
+/*!
+ * Read-only cache backed by a memory-mapped index file.
+ *
+ * The index is a sequence of little-endian `u32` words:
+ *
+ * ```text
+ * header      magic "MRKI", version, state count, symbol count and
+ *             fingerprint of the model's symbols (see
+ *             `Interner::fingerprint`)
+ * offsets     state count + 1 u64 offsets (in words, from the start of
+ *             the file), the last one pointing at the end of the file
+ * states      for each state, sorted by key:
+ *                 key length, key symbols,
+ *                 transition count, then (symbol, count) pairs
+ * ```
+ *
+ * Lookups binary-search the sorted states directly in the mapped memory.
+ * Opening an index only checks its header, each state being checked when it
+ * is read: corrupted states are treated as missing (`validate` checks them
+ * all up front). The symbols used in the index are those of the model it was
+ * built from: use `MarkovGenerator::load_with_cache` to pair them again,
+ * which checks the fingerprint and the symbol count.
+ */
+
+use libc;
+use std::io::{
+    fs,
+    BufferedWriter,
+    File,
+    IoError,
+    IoResult,
+};
+use std::mem;
+use std::os::{
+    MapOption,
+    MemoryMap,
+};
+use std::raw;
+
+use {
+    Cache,
+    Interner,
+    ModelError,
+    Symbol,
+    Transition,
+};
+
+pub const MAGIC: &'static [u8] = b"MRKI";
+pub const VERSION: u32 = 1;
+
+const HEADER_WORDS: uint = 5;
+
+pub struct MappedCache {
+    map: MemoryMap,
+    count: uint,
+    symbols: uint,
+    fingerprint: u32,
+}
+
+impl MappedCache {
+    /// Map an index written by `MappedCache::write`.
+    pub fn open(path: &Path) -> Result<MappedCache, ModelError> {
+        if cfg!(target_endian = "big") {
+            return Err(ModelError::Corrupted("mapped indexes require a little-endian target".to_string()));
+        }
+
+        let len = try!(fs::stat(path)).size as uint;
+        if len < HEADER_WORDS * 4 || len % 4 != 0 {
+            return Err(ModelError::BadMagic);
+        }
+
+        let fd = path.with_c_str(|path| unsafe { libc::open(path, libc::O_RDONLY, 0) });
+        if fd < 0 {
+            return Err(ModelError::Io(IoError::last_error()));
+        }
+        let map = MemoryMap::new(len, &[MapOption::MapReadable, MapOption::MapFd(fd)]);
+        unsafe { libc::close(fd); }
+
+        let map = match map {
+            Ok(map) => map,
+            Err(err) => return Err(ModelError::Corrupted(format!("cannot map index: {}", err))),
+        };
+        let mut cache = MappedCache {
+            map: map,
+            count: 0,
+            symbols: 0,
+            fingerprint: 0,
+        };
+
+        if cache.bytes()[..4] != MAGIC {
+            return Err(ModelError::BadMagic);
+        }
+        let version = cache.words()[1];
+        if version != VERSION {
+            return Err(ModelError::UnsupportedVersion(version as u16));
+        }
+
+        let count = cache.words()[2] as uint;
+        if cache.words().len() < HEADER_WORDS + 2 * (count + 1) || cache.offset(count) != cache.words().len() {
+            return Err(ModelError::Corrupted("truncated index".to_string()));
+        }
+        cache.count = count;
+        cache.symbols = cache.words()[3] as uint;
+        cache.fingerprint = cache.words()[4];
+
+        Ok(cache)
+    }
+
+    /// Check every state of the index, instead of each one as it is read.
+    pub fn validate(&self) -> Result<(), ModelError> {
+        for index in range(0, self.count) {
+            if self.state(index).is_none() {
+                return Err(ModelError::Corrupted(format!("state {} of the index", index)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write an index of `cache`, whose symbols are those of `symbols`, that
+    /// can later be opened with `MappedCache::open`.
+    pub fn write<C: Cache>(cache: &C, symbols: &Interner, writer: &mut Writer) -> IoResult<()> {
+        let mut states: Vec<(&[Symbol], &[Transition])> = cache.states().collect();
+        states.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
+
+        try!(writer.write(MAGIC));
+        try!(writer.write_le_u32(VERSION));
+        try!(writer.write_le_u32(states.len() as u32));
+        try!(writer.write_le_u32(symbols.len() as u32));
+        try!(writer.write_le_u32(symbols.fingerprint()));
+
+        let mut offset = (HEADER_WORDS + 2 * (states.len() + 1)) as u64;
+        for &(key, transitions) in states.iter() {
+            try!(writer.write_le_u64(offset));
+            offset += (2 + key.len() + 2 * transitions.len()) as u64;
+        }
+        try!(writer.write_le_u64(offset));
+
+        for &(key, transitions) in states.iter() {
+            try!(writer.write_le_u32(key.len() as u32));
+            for &symbol in key.iter() {
+                try!(writer.write_le_u32(symbol));
+            }
+
+            try!(writer.write_le_u32(transitions.len() as u32));
+            for transition in transitions.iter() {
+                try!(writer.write_le_u32(transition.symbol));
+                try!(writer.write_le_u32(transition.count));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn create<C: Cache>(cache: &C, symbols: &Interner, path: &Path) -> IoResult<()> {
+        let mut writer = BufferedWriter::new(try!(File::create(path)));
+        try!(MappedCache::write(cache, symbols, &mut writer));
+        writer.flush()
+    }
+
+    pub fn len(&self) -> uint {
+        self.count
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe {
+            mem::transmute(raw::Slice {
+                data: self.map.data() as *const u8,
+                len: self.map.len(),
+            })
+        }
+    }
+
+    fn words(&self) -> &[u32] {
+        unsafe {
+            mem::transmute(raw::Slice {
+                data: self.map.data() as *const u32,
+                len: self.map.len() / 4,
+            })
+        }
+    }
+
+    fn offset(&self, index: uint) -> uint {
+        let words = self.words();
+        let at = HEADER_WORDS + 2 * index;
+
+        (words[at] as u64 | (words[at + 1] as u64 << 32)) as uint
+    }
+
+    /// State `index`, or `None` if its layout is corrupted or it uses
+    /// symbols the model does not have.
+    fn state(&self, index: uint) -> Option<(&[Symbol], &[Transition])> {
+        let (start, end) = (self.offset(index), self.offset(index + 1));
+        if start < HEADER_WORDS + 2 * (self.count + 1) || end > self.words().len() || end < start || end - start < 2 {
+            return None;
+        }
+        let words = self.words()[start..end];
+
+        let key_len = words[0] as uint;
+        if key_len > words.len() - 2 {
+            return None;
+        }
+        let key = words[1..1 + key_len];
+        let count = words[1 + key_len] as uint;
+        let pairs = words[2 + key_len..];
+        if pairs.len() % 2 != 0 || pairs.len() / 2 != count {
+            return None;
+        }
+
+        let transitions: &[Transition] = unsafe {
+            mem::transmute(raw::Slice {
+                data: pairs.as_ptr() as *const Transition,
+                len: count,
+            })
+        };
+
+        let symbols = self.symbols as u64;
+        if key.iter().any(|&symbol| symbol as u64 >= symbols) ||
+            transitions.iter().any(|transition| transition.symbol as u64 >= symbols) {
+            return None;
+        }
+
+        Some((key, transitions))
+    }
+}
+
+impl Cache for MappedCache {
+    fn get(&self, key: &[Symbol]) -> Option<&[Transition]> {
+        let mut low = 0;
+        let mut high = self.count;
+
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let (state, transitions) = match self.state(middle) {
+                Some(state) => state,
+                None => return None,
+            };
+
+            match state.cmp(key) {
+                Less => low = middle + 1,
+                Greater => high = middle,
+                Equal => return Some(transitions),
+            }
+        }
+
+        None
+    }
+
+    fn fingerprint(&self) -> Option<(u32, uint)> {
+        Some((self.fingerprint, self.symbols))
+    }
+
+    fn states<'a>(&'a self) -> Box<Iterator<(&'a [Symbol], &'a [Transition])> + 'a> {
+        box MappedStates {
+            cache: self,
+            index: 0,
+        }
+    }
+}
+
+struct MappedStates<'a> {
+    cache: &'a MappedCache,
+    index: uint,
+}
+
+impl<'a> Iterator<(&'a [Symbol], &'a [Transition])> for MappedStates<'a> {
+    fn next(&mut self) -> Option<(&'a [Symbol], &'a [Transition])> {
+        // Corrupted states are skipped, as lookups do not find them either.
+        while self.index < self.cache.count {
+            let state = self.cache.state(self.index);
+            self.index += 1;
+            if state.is_some() {
+                return state;
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{
+        File,
+        MemReader,
+        MemWriter,
+        TempDir,
+    };
+
+    use super::{
+        MappedCache,
+        HEADER_WORDS,
+    };
+    use testing::Model;
+    use {
+        Cache,
+        MarkovGenerator,
+        ModelError,
+    };
+
+    fn model(words: &[&str]) -> Model {
+        let mut markov = MarkovGenerator::with_order(HashMap::new(), 2);
+        markov.sentences = true;
+        markov.feed_document(words);
+        markov
+    }
+
+    fn index(markov: &Model) -> Vec<u8> {
+        let mut writer = MemWriter::new();
+        MappedCache::write(&markov.cache, &markov.symbols, &mut writer).unwrap();
+        writer.unwrap()
+    }
+
+    fn open(bytes: &[u8]) -> Result<MappedCache, ModelError> {
+        let dir = TempDir::new("markov-mapped").unwrap();
+        let path = dir.path().join("index");
+        File::create(&path).write(bytes).unwrap();
+        MappedCache::open(&path)
+    }
+
+    fn patch(bytes: &mut Vec<u8>, word: uint, value: u32) {
+        for byte in range(0, 4) {
+            bytes.as_mut_slice()[4 * word + byte] = (value >> 8 * byte) as u8;
+        }
+    }
+
+    fn save(markov: &Model) -> Vec<u8> {
+        let mut writer = MemWriter::new();
+        markov.save(&mut writer).unwrap();
+        writer.unwrap()
+    }
+
+    #[test]
+    fn round_trip_keeps_every_state() {
+        let markov = model(&["the", "cat", "sat", "on", "the", "mat"]);
+        let cache = open(index(&markov).as_slice()).unwrap();
+        cache.validate().unwrap();
+
+        assert_eq!(cache.len(), markov.cache.states().count());
+        for (key, transitions) in markov.cache.states() {
+            assert_eq!(cache.get(key), Some(transitions));
+        }
+        assert_eq!(cache.get(&[42, 42]), None);
+
+        let loaded = MarkovGenerator::load_with_cache(&mut MemReader::new(save(&markov)), cache).unwrap();
+        assert_eq!(loaded.words, markov.words);
+        assert_eq!(loaded.symbols.names(), markov.symbols.names());
+    }
+
+    #[test]
+    fn symbols_can_be_loaded_without_the_words() {
+        let markov = model(&["the", "cat", "sat"]);
+        let cache = open(index(&markov).as_slice()).unwrap();
+
+        let loaded = MarkovGenerator::load_symbols_with_cache(&mut MemReader::new(save(&markov)), cache).unwrap();
+        assert!(loaded.words.is_empty());
+        assert_eq!(loaded.symbols.names(), markov.symbols.names());
+    }
+
+    #[test]
+    fn truncated_index_is_rejected() {
+        let mut bytes = index(&model(&["the", "cat", "sat"]));
+        let len = bytes.len();
+        bytes.truncate(len - 4);
+
+        match open(bytes.as_slice()) {
+            Err(ModelError::Corrupted(..)) => {}
+            _ => panic!("truncated index opened"),
+        }
+    }
+
+    #[test]
+    fn bad_offsets_hide_the_state() {
+        let markov = model(&["the", "cat", "sat"]);
+        let mut bytes = index(&markov);
+        patch(&mut bytes, HEADER_WORDS, 0xffffffff);
+
+        let cache = open(bytes.as_slice()).unwrap();
+        assert!(cache.validate().is_err());
+        assert_eq!(cache.states().count(), markov.cache.states().count() - 1);
+    }
+
+    #[test]
+    fn unknown_symbols_hide_the_state() {
+        let markov = model(&["the", "cat", "sat"]);
+        let mut bytes = index(&markov);
+        // Symbol of the last transition of the last state.
+        let last = bytes.len() / 4 - 2;
+        patch(&mut bytes, last, 1000);
+
+        let cache = open(bytes.as_slice()).unwrap();
+        assert!(cache.validate().is_err());
+        assert_eq!(cache.states().count(), markov.cache.states().count() - 1);
+    }
+
+    #[test]
+    fn another_model_is_incompatible() {
+        let markov = model(&["the", "cat", "sat"]);
+        let cache = open(index(&model(&["a", "dog", "ran"])).as_slice()).unwrap();
+
+        match MarkovGenerator::load_with_cache(&mut MemReader::new(save(&markov)), cache) {
+            Err(ModelError::Incompatible(..)) => {}
+            _ => panic!("paired with another model's index"),
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use std::rand::{
+    task_rng,
+    Rng,
+};
+
+use {
+    gen_index,
+    text_len,
+    truncate_text,
+    Cache,
+    CacheMut,
+    Interner,
+    MarkovGenerator,
+    ModelError,
+    Symbol,
+    DOCUMENT_BOUNDARY,
+};
+
+impl<C> MarkovGenerator<C> where C: CacheMut {
+    /// Add the transitions and words of `other` to this model, as if its
+    /// corpus had been fed here too, as a separate document. Both models must
+    /// have the same orders and tokenizer.
+    pub fn merge<D: Cache>(&mut self, other: &MarkovGenerator<D>) -> Result<(), ModelError> {
+        if other.order != self.order || other.min_order != self.min_order {
+            let detail = format!("orders {}..{} and {}..{} differ",
+                                 self.min_order, self.order, other.min_order, other.order);
+            return Err(ModelError::Incompatible(detail));
+        }
+        if other.sentences != self.sentences {
+            return Err(ModelError::Incompatible("only one model uses sentence markers".to_string()));
+        }
+        if other.tokenizer.name() != self.tokenizer.name() {
+            let detail = format!("tokenizers `{}` and `{}` differ", self.tokenizer.name(), other.tokenizer.name());
+            return Err(ModelError::Incompatible(detail));
+        }
+
+        // Markers have the same symbols in every interner.
+        let symbols: Vec<Symbol> = other.symbols.names().iter().enumerate().map(|(symbol, name)| {
+            let symbol = symbol as Symbol;
+            if Interner::is_marker(symbol) { symbol } else { self.symbols.intern(name.as_slice()) }
+        }).collect();
+        let translate = |key: &[Symbol]| -> Vec<Symbol> {
+            key.iter().map(|&symbol| symbols[symbol as uint]).collect()
+        };
+
+        for (key, transitions) in other.cache.states() {
+            let key = translate(key);
+            for transition in transitions.iter() {
+                self.cache.add(key.as_slice(), symbols[transition.symbol as uint], transition.count);
+            }
+        }
+
+        let words = translate(other.words.as_slice());
+        match (&mut self.backward, &other.backward) {
+            (&Some(ref mut backward), &Some(ref other_backward)) => {
+                for (key, transitions) in other_backward.states() {
+                    let key = translate(key);
+                    for transition in transitions.iter() {
+                        backward.add(key.as_slice(), symbols[transition.symbol as uint], transition.count);
+                    }
+                }
+            }
+            (&Some(ref mut backward), &None) => {
+                for ngram in words.as_slice().windows(self.order + 1) {
+                    let key: Vec<Symbol> = ngram.tail().iter().rev().map(|&symbol| symbol).collect();
+                    backward.put(key.as_slice(), ngram[0]);
+                }
+            }
+            _ => {}
+        }
+        // No window may bridge the words of both models.
+        self.end_document();
+        self.words.extend(words.into_iter());
+        self.invalidate_positions();
+
+        if self.seeds.is_some() {
+            self.rebuild_seeds();
+        }
+
+        Ok(())
+    }
+}
+
+/// Generator sampling each step from a weighted mixture of several models.
+///
+/// Models are matched on the text of their tokens, so they may have been
+/// trained separately, with different orders. Each step, every model that
+/// knows the current state contributes its successor distribution, scaled
+/// by its weight.
+pub struct Mixture<'a, C: 'a> {
+    models: Vec<(&'a MarkovGenerator<C>, f64)>,
+}
+
+/// Token generated by a mixture: markers have the same symbol in every
+/// model, text is matched on its name.
+#[deriving(Clone, PartialEq)]
+enum Token<'a> {
+    Marker(Symbol),
+    Text(&'a str),
+}
+
+impl<'a> Token<'a> {
+    fn of<C: Cache>(model: &'a MarkovGenerator<C>, symbol: Symbol) -> Token<'a> {
+        if Interner::is_marker(symbol) {
+            Token::Marker(symbol)
+        } else {
+            Token::Text(model.symbols.resolve(symbol))
+        }
+    }
+
+    /// Symbol of the token in `model`, if it knows it.
+    fn symbol<C: Cache>(&self, model: &MarkovGenerator<C>) -> Option<Symbol> {
+        match *self {
+            Token::Marker(symbol) => Some(symbol),
+            Token::Text(text) => model.symbols.get(text),
+        }
+    }
+}
+
+impl<'a, C> Mixture<'a, C> where C: Cache {
+    pub fn new() -> Mixture<'a, C> {
+        Mixture {
+            models: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, model: &'a MarkovGenerator<C>, weight: f64) {
+        self.models.push((model, weight));
+    }
+
+    pub fn generate_text(&self, size: uint) -> String {
+        self.generate_text_with_rng(size, &mut task_rng())
+    }
+
+    /// Generate up to `size` words, seeded from a model picked by weight and
+    /// joined with the detokenizer of the first model.
+    pub fn generate_text_with_rng<R: Rng>(&self, size: uint, rng: &mut R) -> String {
+        let seed_model = match self.pick_model(rng) {
+            Some(model) => model,
+            None => return String::new(),
+        };
+        if seed_model.words.len() <= seed_model.order {
+            return String::new();
+        }
+
+        let seed = gen_index(rng, seed_model.words.len() - seed_model.order);
+        let seed = seed_model.skip_document_end(seed);
+        let mut symbols = seed_model.words[seed..seed + seed_model.order].to_vec();
+        truncate_text(&mut symbols, size);
+        let mut len = text_len(symbols.as_slice());
+        let mut tokens: Vec<Token<'a>> = symbols.iter().map(|&symbol| Token::of(seed_model, symbol)).collect();
+
+        while len < size {
+            match self.next_token(tokens.as_slice(), rng) {
+                Some(Token::Marker(DOCUMENT_BOUNDARY)) | None => break,
+                Some(token) => {
+                    match token {
+                        Token::Text(..) => len += 1,
+                        Token::Marker(..) => {}
+                    }
+                    tokens.push(token);
+                }
+            }
+        }
+
+        let (first, _) = self.models[0];
+        let text: Vec<&str> = tokens.into_iter().filter_map(|token| match token {
+            Token::Text(text) => Some(text),
+            Token::Marker(..) => None,
+        }).collect();
+        first.detokenize_words(text.as_slice())
+    }
+
+    fn pick_model<R: Rng>(&self, rng: &mut R) -> Option<&'a MarkovGenerator<C>> {
+        let total = self.models.iter().fold(0.0, |total, &(_, weight)| total + weight);
+        if self.models.is_empty() || total <= 0.0 {
+            return None;
+        }
+
+        let mut target = rng.gen::<f64>() * total;
+        for &(model, weight) in self.models.iter() {
+            if target < weight {
+                return Some(model);
+            }
+            target -= weight;
+        }
+
+        self.models.last().map(|&(model, _)| model)
+    }
+
+    /// Pick the next token from the weighted mixture of the successor
+    /// distributions of every model knowing the current state.
+    fn next_token<R: Rng>(&self, tokens: &[Token<'a>], rng: &mut R) -> Option<Token<'a>> {
+        let mut candidates: Vec<(Token<'a>, f64)> = Vec::new();
+
+        for &(model, weight) in self.models.iter() {
+            if tokens.len() < model.order {
+                continue;
+            }
+
+            let mut key = Vec::with_capacity(model.order);
+            for token in tokens[tokens.len() - model.order..].iter() {
+                match token.symbol(model) {
+                    Some(symbol) => key.push(symbol),
+                    None => break,
+                }
+            }
+            if key.len() != model.order {
+                continue;
+            }
+
+            let successors = match model.cache.get(key.as_slice()) {
+                Some(successors) => successors,
+                None => continue,
+            };
+            let total = successors.iter().fold(0u64, |total, transition| total + transition.count as u64);
+
+            for transition in successors.iter() {
+                let token = Token::of(model, transition.symbol);
+                let probability = weight * transition.count as f64 / total as f64;
+
+                match candidates.iter().position(|&(ref candidate, _)| *candidate == token) {
+                    Some(index) => match candidates[index] {
+                        (_, ref mut mass) => *mass += probability,
+                    },
+                    None => candidates.push((token, probability)),
+                }
+            }
+        }
+
+        let total = candidates.iter().fold(0.0, |total, &(_, mass)| total + mass);
+        if total <= 0.0 {
+            return None;
+        }
+
+        let mut target = rng.gen::<f64>() * total;
+        for &(ref token, mass) in candidates.iter() {
+            if target < mass {
+                return Some(token.clone());
+            }
+            target -= mass;
+        }
+
+        candidates.last().map(|&(ref token, _)| token.clone())
+    }
+}
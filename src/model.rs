@@ -0,0 +1,429 @@
+/*!
+ * Binary model format.
+ *
+ * All integers are big-endian, strings are a `u32` byte length followed by
+ * their UTF-8 bytes.
+ *
+ * ```text
+ * magic       "MRKV"
+ * version     u16
+ * order       u32
+ * min order   u32 (lowest order stored in the state table)
+ * tokenizer   string (name of the tokenizer, see `tokenizer_by_name`)
+ * sentences   u8 (1 if fed sentences are wrapped in markers, 0 otherwise)
+ * symbols     u32 count, then one string per symbol (in symbol order,
+ *             starting with the markers, whose names are ignored)
+ * words       u64 count, then one u32 symbol per word
+ * seeds       u8 (1 if the seeds are set, 0 otherwise), then if set
+ *             u64 count and one u64 position per seed
+ * states      u64 count, then for each state:
+ *                 u32 key length, key symbols (u32 each),
+ *                 u32 transition count, then (u32 symbol, u32 count) pairs
+ * ```
+ *
+ * States are written sorted by key so that saving the same model twice
+ * produces the same bytes.
+ */
+
+use std::error::{
+    Error,
+    FromError,
+};
+use std::fmt;
+use std::io::{
+    BufferedReader,
+    BufferedWriter,
+    File,
+    IoError,
+    IoResult,
+};
+use std::io::util::LimitReader;
+
+use {
+    tokenizer_by_name,
+    Cache,
+    CacheMut,
+    Interner,
+    MarkovGenerator,
+    Symbol,
+    Transition,
+};
+
+pub const MAGIC: &'static [u8] = b"MRKV";
+pub const VERSION: u16 = 1;
+
+/// Highest order accepted from a model file. Generation allocates `order`
+/// markers for each state, so a corrupted order must not be trusted.
+pub const MAX_ORDER: uint = 64;
+
+pub enum ModelError {
+    Io(IoError),
+    BadMagic,
+    UnsupportedVersion(u16),
+    Corrupted(String),
+    /// Two models cannot be combined.
+    Incompatible(String),
+}
+
+impl fmt::Show for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ModelError::Io(ref err) => write!(f, "{}", err),
+            ModelError::BadMagic => write!(f, "not a markov model file"),
+            ModelError::UnsupportedVersion(version) => {
+                write!(f, "unsupported model version {} (expected {})", version, VERSION)
+            }
+            ModelError::Corrupted(ref detail) => write!(f, "corrupted model: {}", detail),
+            ModelError::Incompatible(ref detail) => write!(f, "incompatible models: {}", detail),
+        }
+    }
+}
+
+impl Error for ModelError {
+    fn description(&self) -> &str {
+        match *self {
+            ModelError::Io(ref err) => err.description(),
+            ModelError::BadMagic => "not a markov model file",
+            ModelError::UnsupportedVersion(..) => "unsupported model version",
+            ModelError::Corrupted(..) => "corrupted model",
+            ModelError::Incompatible(..) => "incompatible models",
+        }
+    }
+
+    fn detail(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            ModelError::Io(ref err) => Some(err as &Error),
+            _ => None,
+        }
+    }
+}
+
+impl FromError<IoError> for ModelError {
+    fn from_error(err: IoError) -> ModelError {
+        ModelError::Io(err)
+    }
+}
+
+fn corrupted<T>(detail: &str) -> Result<T, ModelError> {
+    Err(ModelError::Corrupted(detail.to_string()))
+}
+
+fn write_string(writer: &mut Writer, s: &str) -> IoResult<()> {
+    try!(writer.write_be_u32(s.len() as u32));
+    writer.write_str(s)
+}
+
+fn read_symbol(reader: &mut Reader, symbols: &Interner) -> Result<Symbol, ModelError> {
+    let symbol = try!(reader.read_be_u32());
+
+    if (symbol as uint) < symbols.len() {
+        Ok(symbol)
+    } else {
+        corrupted("symbol out of range")
+    }
+}
+
+fn read_string(reader: &mut Reader) -> Result<String, ModelError> {
+    // The length is not trusted with an allocation up front: a corrupted one
+    // just ends up with too few bytes.
+    let len = try!(reader.read_be_u32()) as uint;
+    let bytes = try!(LimitReader::new(&mut *reader, len).read_to_end());
+    if bytes.len() != len {
+        return corrupted("truncated string");
+    }
+
+    match String::from_utf8(bytes) {
+        Ok(s) => Ok(s),
+        Err(..) => corrupted("invalid UTF-8 string"),
+    }
+}
+
+impl<C> MarkovGenerator<C> where C: Cache {
+    /// Write the model in the binary format described in the `model` module.
+    pub fn save(&self, writer: &mut Writer) -> IoResult<()> {
+        try!(writer.write(MAGIC));
+        try!(writer.write_be_u16(VERSION));
+        try!(writer.write_be_u32(self.order as u32));
+        try!(writer.write_be_u32(self.min_order as u32));
+        try!(write_string(writer, self.tokenizer.name()));
+        try!(writer.write_u8(if self.sentences { 1 } else { 0 }));
+
+        try!(writer.write_be_u32(self.symbols.len() as u32));
+        for name in self.symbols.names().iter() {
+            try!(write_string(writer, name.as_slice()));
+        }
+
+        try!(writer.write_be_u64(self.words.len() as u64));
+        for &symbol in self.words.iter() {
+            try!(writer.write_be_u32(symbol));
+        }
+
+        match self.seeds {
+            Some(ref seeds) => {
+                try!(writer.write_u8(1));
+                try!(writer.write_be_u64(seeds.len() as u64));
+                for &seed in seeds.iter() {
+                    try!(writer.write_be_u64(seed as u64));
+                }
+            }
+            None => try!(writer.write_u8(0)),
+        }
+
+        let mut states: Vec<(&[Symbol], &[Transition])> = self.cache.states().collect();
+        states.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
+
+        try!(writer.write_be_u64(states.len() as u64));
+        for &(key, transitions) in states.iter() {
+            try!(writer.write_be_u32(key.len() as u32));
+            for &symbol in key.iter() {
+                try!(writer.write_be_u32(symbol));
+            }
+
+            try!(writer.write_be_u32(transitions.len() as u32));
+            for transition in transitions.iter() {
+                try!(writer.write_be_u32(transition.symbol));
+                try!(writer.write_be_u32(transition.count));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read a model written by `save` but keep `cache` instead of loading its
+    /// state table, e.g. a `MappedCache` built from the same model, which is
+    /// checked when the cache records the fingerprint of its symbols. The
+    /// fed words are still read in full, see `load_symbols_with_cache`.
+    pub fn load_with_cache(reader: &mut Reader, cache: C) -> Result<MarkovGenerator<C>, ModelError> {
+        let mut markov = try!(MarkovGenerator::load_symbols_with_cache(reader, cache));
+
+        // Counts read from the input only bound the loops, the vectors growing
+        // as entries are actually read.
+        let word_count = try!(reader.read_be_u64());
+        for _ in range(0, word_count) {
+            let symbol = try!(read_symbol(reader, &markov.symbols));
+            markov.words.push(symbol);
+        }
+
+        if try!(reader.read_u8()) != 0 {
+            let seed_count = try!(reader.read_be_u64());
+            let mut seeds = Vec::new();
+            for _ in range(0, seed_count) {
+                let seed = try!(reader.read_be_u64()) as uint;
+                if seed + markov.order > markov.words.len() {
+                    return corrupted("seed out of range");
+                }
+                seeds.push(seed);
+            }
+            markov.seeds = Some(seeds);
+        }
+
+        Ok(markov)
+    }
+
+    /// Like `load_with_cache`, but stop before the fed words, which are left
+    /// empty. The model can then still score text, or generate from a prompt
+    /// or from sentence starts, without reading the whole corpus.
+    pub fn load_symbols_with_cache(reader: &mut Reader, cache: C) -> Result<MarkovGenerator<C>, ModelError> {
+        let magic = try!(reader.read_exact(MAGIC.len()));
+        if magic.as_slice() != MAGIC {
+            return Err(ModelError::BadMagic);
+        }
+
+        let version = try!(reader.read_be_u16());
+        if version != VERSION {
+            return Err(ModelError::UnsupportedVersion(version));
+        }
+
+        let order = try!(reader.read_be_u32()) as uint;
+        let min_order = try!(reader.read_be_u32()) as uint;
+        if order > MAX_ORDER {
+            return corrupted("order too high");
+        }
+        if min_order > order {
+            return corrupted("minimum order above the order");
+        }
+        let tokenizer = try!(read_string(reader));
+        let tokenizer = match tokenizer_by_name(tokenizer.as_slice()) {
+            Some(tokenizer) => tokenizer,
+            None => return corrupted(format!("unknown tokenizer `{}`", tokenizer).as_slice()),
+        };
+
+        let sentences = try!(reader.read_u8()) != 0;
+
+        let mut markov = MarkovGenerator::with_order(cache, order);
+        markov.tokenizer = tokenizer;
+        markov.sentences = sentences;
+        markov.min_order = min_order;
+
+        let symbol_count = try!(reader.read_be_u32());
+        for symbol in range(0, symbol_count) {
+            let name = try!(read_string(reader));
+            if Interner::is_marker(symbol) {
+                continue;
+            }
+            if markov.symbols.intern(name.as_slice()) != symbol {
+                return corrupted("duplicate symbol");
+            }
+        }
+        match markov.cache.fingerprint() {
+            Some((fingerprint, len)) if fingerprint != markov.symbols.fingerprint() ||
+                                        len != markov.symbols.len() => {
+                return Err(ModelError::Incompatible("the cache was built from another model".to_string()));
+            }
+            _ => {}
+        }
+
+        Ok(markov)
+    }
+
+    pub fn save_to_file(&self, path: &Path) -> IoResult<()> {
+        let mut writer = BufferedWriter::new(try!(File::create(path)));
+        try!(self.save(&mut writer));
+        writer.flush()
+    }
+
+    pub fn load_with_cache_from_file(path: &Path, cache: C) -> Result<MarkovGenerator<C>, ModelError> {
+        let mut reader = BufferedReader::new(try!(File::open(path)));
+        MarkovGenerator::load_with_cache(&mut reader, cache)
+    }
+
+    pub fn load_symbols_with_cache_from_file(path: &Path, cache: C) -> Result<MarkovGenerator<C>, ModelError> {
+        let mut reader = BufferedReader::new(try!(File::open(path)));
+        MarkovGenerator::load_symbols_with_cache(&mut reader, cache)
+    }
+}
+
+impl<C> MarkovGenerator<C> where C: CacheMut {
+    /// Read a model written by `save`, filling the given (empty) cache.
+    pub fn load(reader: &mut Reader, cache: C) -> Result<MarkovGenerator<C>, ModelError> {
+        let mut markov = try!(MarkovGenerator::load_with_cache(reader, cache));
+
+        let state_count = try!(reader.read_be_u64());
+        let mut key = Vec::new();
+        for _ in range(0, state_count) {
+            key.clear();
+            let key_len = try!(reader.read_be_u32()) as uint;
+            if key_len < markov.min_order || key_len > markov.order {
+                return corrupted("key length out of the stored orders");
+            }
+            for _ in range(0, key_len) {
+                key.push(try!(read_symbol(reader, &markov.symbols)));
+            }
+
+            let transition_count = try!(reader.read_be_u32());
+            for _ in range(0, transition_count) {
+                let symbol = try!(read_symbol(reader, &markov.symbols));
+                let count = try!(reader.read_be_u32());
+                markov.cache.add(key.as_slice(), symbol, count);
+            }
+        }
+
+        Ok(markov)
+    }
+
+    pub fn load_from_file(path: &Path, cache: C) -> Result<MarkovGenerator<C>, ModelError> {
+        let mut reader = BufferedReader::new(try!(File::open(path)));
+        MarkovGenerator::load(&mut reader, cache)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{
+        MemReader,
+        MemWriter,
+    };
+
+    use super::{
+        ModelError,
+        MAGIC,
+        VERSION,
+    };
+    use testing::{
+        assert_same,
+        fixture,
+        Model,
+    };
+    use MarkovGenerator;
+
+    fn save(markov: &Model) -> Vec<u8> {
+        let mut writer = MemWriter::new();
+        markov.save(&mut writer).unwrap();
+        writer.unwrap()
+    }
+
+    fn load(bytes: Vec<u8>) -> Result<Model, ModelError> {
+        MarkovGenerator::load(&mut MemReader::new(bytes), HashMap::new())
+    }
+
+    #[test]
+    fn round_trip_keeps_the_model() {
+        let markov = fixture();
+
+        let bytes = save(&markov);
+        let loaded = match load(bytes.clone()) {
+            Ok(loaded) => loaded,
+            Err(err) => panic!("load failed: {}", err),
+        };
+
+        assert_same(&loaded, &markov);
+        // States are sorted, so saving again gives the same bytes.
+        assert_eq!(save(&loaded), bytes);
+    }
+
+    /// Start of a model file of order `order`, up to the symbol table.
+    fn header(order: u32) -> MemWriter {
+        let mut writer = MemWriter::new();
+        writer.write(MAGIC).unwrap();
+        writer.write_be_u16(VERSION).unwrap();
+        writer.write_be_u32(order).unwrap();
+        writer.write_be_u32(order).unwrap();
+        writer.write_be_u32(10).unwrap();
+        writer.write(b"whitespace").unwrap();
+        writer.write_u8(0).unwrap();
+        writer
+    }
+
+    #[test]
+    fn huge_counts_fail_without_allocating() {
+        let mut writer = header(2);
+        writer.write_be_u32(0).unwrap();
+        writer.write_be_u64(1 << 60).unwrap();
+
+        assert!(load(writer.unwrap()).is_err());
+    }
+
+    #[test]
+    fn huge_orders_are_rejected() {
+        let mut writer = header(0xffffffff);
+        writer.write_be_u32(0).unwrap();
+        writer.write_be_u64(0).unwrap();
+        writer.write_u8(0).unwrap();
+        writer.write_be_u64(0).unwrap();
+
+        match load(writer.unwrap()) {
+            Err(ModelError::Corrupted(..)) => {}
+            _ => panic!("loaded an order of 2^32 - 1"),
+        }
+    }
+
+    #[test]
+    fn keys_must_match_the_stored_orders() {
+        let mut writer = header(2);
+        writer.write_be_u32(0).unwrap();
+        writer.write_be_u64(0).unwrap();
+        writer.write_u8(0).unwrap();
+        writer.write_be_u64(1).unwrap();
+        writer.write_be_u32(3).unwrap();
+
+        match load(writer.unwrap()) {
+            Err(ModelError::Corrupted(..)) => {}
+            _ => panic!("loaded a key longer than the order"),
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::rand::Rng;
+use std::rc::Rc;
+use std::uint;
+
+use {
+    Cache,
+    MarkovGenerator,
+    Symbol,
+};
+
+/// Limits on how much of the fed words a generated text may copy verbatim.
+#[deriving(Clone, PartialEq, Show)]
+pub struct OverlapGuard {
+    /// Longest run of consecutive words allowed to match the fed words.
+    /// Successors of a state always extend a run to `order + 1` words, so
+    /// lower values act as `order + 1`.
+    pub max_run: uint,
+    /// Reject texts whose longest run is more than this fraction of their
+    /// length.
+    pub max_ratio: Option<f64>,
+    /// Number of texts to generate before giving up on `max_ratio`, the one
+    /// with the lowest ratio being kept.
+    pub attempts: uint,
+}
+
+impl OverlapGuard {
+    pub fn new(max_run: uint) -> OverlapGuard {
+        OverlapGuard {
+            max_run: max_run,
+            max_ratio: None,
+            attempts: 10,
+        }
+    }
+}
+
+/// How much of a generated text was copied from the fed words.
+#[deriving(Clone, PartialEq, Show)]
+pub struct OverlapStats {
+    /// Longest run of consecutive generated words also found, in the same
+    /// order, in the fed words.
+    pub longest_run: uint,
+    /// `longest_run` over the number of generated words, 0 for an empty text.
+    pub ratio: f64,
+    /// Number of texts generated before one was accepted.
+    pub attempts: uint,
+}
+
+/// Positions of every occurrence of each fed word.
+pub type Positions = HashMap<Symbol, Vec<uint>>;
+
+fn index_positions(words: &[Symbol]) -> Positions {
+    let mut positions: Positions = HashMap::new();
+    for (position, &symbol) in words.iter().enumerate() {
+        match positions.get_mut(&symbol) {
+            Some(occurrences) => {
+                occurrences.push(position);
+                continue;
+            }
+            None => {}
+        }
+        positions.insert(symbol, vec![position]);
+    }
+    positions
+}
+
+/// Fed words and their positions, to measure the runs copied from them.
+pub struct Guard<'a> {
+    words: &'a [Symbol],
+    positions: Rc<Positions>,
+    max_run: uint,
+}
+
+impl<'a> Guard<'a> {
+    pub fn new(words: &'a [Symbol], positions: Rc<Positions>, max_run: uint) -> Guard<'a> {
+        Guard {
+            words: words,
+            positions: positions,
+            max_run: max_run,
+        }
+    }
+
+    /// Whether appending `next` to `symbols` keeps every copied run within
+    /// the limit.
+    pub fn allows(&self, symbols: &[Symbol], next: Symbol) -> bool {
+        self.run_ending(symbols, next, cmp::min(self.max_run, uint::MAX - 1) + 1) <= self.max_run
+    }
+
+    /// Length of the longest run of the fed words ending with `symbols`
+    /// followed by `next`, counting no further than `limit`.
+    fn run_ending(&self, symbols: &[Symbol], next: Symbol, limit: uint) -> uint {
+        let positions = match self.positions.get(&next) {
+            Some(positions) => positions,
+            None => return 0,
+        };
+
+        let mut longest = 0;
+        for &position in positions.iter() {
+            let mut len = 1;
+            while len < limit && len <= position && len <= symbols.len() &&
+                  self.words[position - len] == symbols[symbols.len() - len] {
+                len += 1;
+            }
+
+            longest = cmp::max(longest, len);
+            if longest >= limit {
+                break;
+            }
+        }
+
+        longest
+    }
+
+    fn stats(&self, symbols: &[Symbol], attempts: uint) -> OverlapStats {
+        let mut run = 0;
+        let mut longest_run = 0;
+        for end in range(0, symbols.len()) {
+            // A run ending here extends the one ending at the previous word
+            // by one word at most, which bounds the scan.
+            run = self.run_ending(symbols[..end], symbols[end], run + 1);
+            longest_run = cmp::max(longest_run, run);
+        }
+
+        OverlapStats {
+            longest_run: longest_run,
+            ratio: if symbols.is_empty() { 0.0 } else { longest_run as f64 / symbols.len() as f64 },
+            attempts: attempts,
+        }
+    }
+}
+
+impl<C> MarkovGenerator<C> where C: Cache {
+    /// Index of the fed words, built on first use and kept until they change.
+    fn positions(&self) -> Rc<Positions> {
+        let mut cached = self.positions.borrow_mut();
+        match *cached {
+            Some(ref positions) => return positions.clone(),
+            None => {}
+        }
+
+        let positions = Rc::new(index_positions(self.words.as_slice()));
+        *cached = Some(positions.clone());
+        positions
+    }
+
+    /// Generate up to `size` words like `generate_text`, along with how much
+    /// of them was copied from the fed words.
+    ///
+    /// With `overlap_guard` set, successors making a run longer than
+    /// `max_run` are resampled, and whole texts over `max_ratio` regenerated.
+    pub fn generate_text_with_stats<R: Rng>(&self, size: uint, rng: &mut R) -> (String, OverlapStats) {
+        let options = match self.overlap_guard {
+            Some(ref options) => options,
+            None => {
+                let symbols = self.generate_symbols(size, rng, None);
+                let guard = Guard::new(self.words.as_slice(), self.positions(), uint::MAX);
+                let stats = guard.stats(symbols.as_slice(), 1);
+                return (self.detokenize(symbols.as_slice()), stats);
+            }
+        };
+        let guard = Guard::new(self.words.as_slice(), self.positions(), cmp::max(options.max_run, self.order + 1));
+
+        let mut best: Option<(Vec<Symbol>, OverlapStats)> = None;
+        let mut attempts = 0;
+        while attempts < cmp::max(options.attempts, 1) {
+            attempts += 1;
+            let symbols = self.generate_symbols(size, rng, Some(&guard));
+            let stats = guard.stats(symbols.as_slice(), attempts);
+            let accepted = options.max_ratio.map_or(true, |max_ratio| stats.ratio <= max_ratio);
+
+            let better = match best {
+                Some((_, ref best)) => stats.ratio < best.ratio,
+                None => true,
+            };
+            if better {
+                best = Some((symbols, stats));
+            }
+            if accepted {
+                break;
+            }
+        }
+
+        let (symbols, mut stats) = best.unwrap();
+        stats.attempts = attempts;
+        (self.detokenize(symbols.as_slice()), stats)
+    }
+}
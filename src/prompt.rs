@@ -0,0 +1,229 @@
+use std::cmp;
+use std::error::Error;
+use std::fmt;
+use std::rand::{
+    task_rng,
+    Rng,
+};
+
+use {
+    gen_index,
+    text_len,
+    truncate_text,
+    Cache,
+    Interner,
+    MarkovGenerator,
+    Symbol,
+    DOCUMENT_BOUNDARY,
+    SENTENCE_END,
+    SENTENCE_START,
+};
+
+/// What to do when the state made of the end of a prefix was never seen.
+#[deriving(Clone, PartialEq, Show)]
+pub enum PrefixFallback {
+    /// Give up with `GenerateError::UnknownPrefix`.
+    Fail,
+    /// Match shorter and shorter suffixes of the prefix against the corpus,
+    /// down to its last word only.
+    Shorten,
+}
+
+pub enum GenerateError {
+    EmptyPrefix,
+    UnknownPrefix(String),
+    UnknownWord(String),
+    NoBackwardChain,
+}
+
+impl fmt::Show for GenerateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GenerateError::EmptyPrefix => write!(f, "empty prefix"),
+            GenerateError::UnknownPrefix(ref prefix) => {
+                write!(f, "no state of the model matches the prefix `{}`", prefix)
+            }
+            GenerateError::UnknownWord(ref word) => write!(f, "`{}` was never fed to the model", word),
+            GenerateError::NoBackwardChain => write!(f, "the model has no backward chain"),
+        }
+    }
+}
+
+impl Error for GenerateError {
+    fn description(&self) -> &str {
+        match *self {
+            GenerateError::EmptyPrefix => "empty prefix",
+            GenerateError::UnknownPrefix(..) => "unknown prefix",
+            GenerateError::UnknownWord(..) => "unknown word",
+            GenerateError::NoBackwardChain => "no backward chain",
+        }
+    }
+
+    fn detail(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+}
+
+impl<C> MarkovGenerator<C> where C: Cache {
+    /// Generate up to `size` words continuing `prefix`, which is kept at the
+    /// start of the text.
+    pub fn generate_from(&self, prefix: &[&str], size: uint, fallback: PrefixFallback)
+                         -> Result<String, GenerateError> {
+        self.generate_from_with_rng(prefix, size, fallback, &mut task_rng())
+    }
+
+    pub fn generate_from_with_rng<R: Rng>(&self, prefix: &[&str], size: uint,
+                                          fallback: PrefixFallback, rng: &mut R)
+                                          -> Result<String, GenerateError> {
+        if prefix.is_empty() {
+            return Err(GenerateError::EmptyPrefix);
+        }
+
+        let mut symbols = match self.prefix_state(prefix, fallback, rng) {
+            Some(state) => state,
+            None => return Err(GenerateError::UnknownPrefix(prefix.connect(" "))),
+        };
+        let start = symbols.len();
+        let mut len = 0;
+
+        while len < size {
+            match self.next_symbol(&self.cache, symbols.as_slice(), rng) {
+                Some(DOCUMENT_BOUNDARY) | None => break,
+                Some(symbol) => {
+                    symbols.push(symbol);
+                    if !Interner::is_marker(symbol) {
+                        len += 1;
+                    }
+                }
+            }
+        }
+
+        let mut words = prefix.to_vec();
+        words.extend(self.resolve_text(symbols[start..]).into_iter());
+        Ok(self.detokenize_words(words.as_slice()))
+    }
+
+    /// Find the state to continue `prefix` from.
+    fn prefix_state<R: Rng>(&self, prefix: &[&str], fallback: PrefixFallback, rng: &mut R)
+                            -> Option<Vec<Symbol>> {
+        let mut known = Vec::new();
+        for &word in prefix.iter().rev() {
+            match self.symbols.get(word) {
+                Some(symbol) => known.insert(0, symbol),
+                None => break,
+            }
+        }
+
+        if known.len() >= self.order {
+            let state = known[known.len() - self.order..];
+            if self.order == 0 || self.cache.has(state) {
+                return Some(state.to_vec());
+            }
+        }
+
+        if fallback == PrefixFallback::Fail {
+            return None;
+        }
+
+        // Look for the longest suffix of the prefix in the corpus, and use the
+        // state ending at a random occurrence of it.
+        let longest = if known.len() >= self.order { self.order - 1 } else { known.len() };
+        for len in range(1, longest + 1).rev() {
+            let suffix = known[known.len() - len..];
+            let candidates: Vec<uint> = range(self.order, self.words.len() + 1).filter(|&end| {
+                self.words[end - len..end] == suffix && self.cache.has(self.words[end - self.order..end])
+            }).collect();
+
+            if !candidates.is_empty() {
+                let end = candidates[gen_index(rng, candidates.len())];
+                return Some(self.words[end - self.order..end].to_vec());
+            }
+        }
+
+        None
+    }
+
+    /// Generate about `size` words around `keyword`, growing the text to the
+    /// right with the forward chain and to the left with the `backward` one.
+    pub fn generate_around(&self, keyword: &str, size: uint) -> Result<String, GenerateError> {
+        self.generate_around_with_rng(keyword, size, &mut task_rng())
+    }
+
+    pub fn generate_around_with_rng<R: Rng>(&self, keyword: &str, size: uint, rng: &mut R)
+                                            -> Result<String, GenerateError> {
+        let backward = match self.backward {
+            Some(ref backward) => backward,
+            None => return Err(GenerateError::NoBackwardChain),
+        };
+        let unknown = || GenerateError::UnknownWord(keyword.to_string());
+        let symbol = match self.symbols.get(keyword) {
+            Some(symbol) => symbol,
+            None => return Err(unknown()),
+        };
+
+        // Start from an occurrence of the keyword followed by enough words to
+        // make a state for both directions, markers being no text to show.
+        let span = cmp::max(self.order, 1);
+        let candidates: Vec<uint> = range(0, self.words.len()).filter(|&start| {
+            self.words[start] == symbol && start + span <= self.words.len() &&
+                !self.words[start..start + span].iter().any(|&symbol| Interner::is_marker(symbol))
+        }).collect();
+        if candidates.is_empty() {
+            return Err(unknown());
+        }
+        let start = candidates[gen_index(rng, candidates.len())];
+        let core = self.words[start..start + span];
+
+        let mut right = core.to_vec();
+        while text_len(right.as_slice()) < size - size / 2 {
+            match self.next_symbol(&self.cache, right.as_slice(), rng) {
+                Some(SENTENCE_END) if self.sentences => break,
+                Some(DOCUMENT_BOUNDARY) => break,
+                Some(symbol) => right.push(symbol),
+                None => break,
+            }
+        }
+
+        let mut left: Vec<Symbol> = core.iter().rev().map(|&symbol| symbol).collect();
+        while text_len(left[span..]) + text_len(right.as_slice()) < size {
+            match self.next_symbol(backward, left.as_slice(), rng) {
+                Some(SENTENCE_START) if self.sentences => break,
+                Some(DOCUMENT_BOUNDARY) => break,
+                Some(symbol) => left.push(symbol),
+                None => break,
+            }
+        }
+
+        // The core alone may hold more than `size` words, of which the
+        // keyword comes first.
+        let mut symbols: Vec<Symbol> = left[span..].iter().rev().map(|&symbol| symbol).collect();
+        truncate_text(&mut right, size - text_len(symbols.as_slice()));
+        symbols.extend(right.into_iter());
+        Ok(self.detokenize(symbols.as_slice()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use testing::Model;
+    use {
+        seeded_rng,
+        MarkovGenerator,
+    };
+
+    #[test]
+    fn generate_around_honours_the_size() {
+        let mut markov: Model = MarkovGenerator::with_order(HashMap::new(), 2);
+        markov.feed_from_words(&["a", "b", "c", "d", "e", "f", "g", "h"]);
+        markov.rebuild_backward(HashMap::new());
+
+        let around = |size: uint| markov.generate_around_with_rng("d", size, &mut seeded_rng(5)).unwrap();
+        assert_eq!(around(0).as_slice(), "");
+        assert_eq!(around(1).as_slice(), "d");
+        assert_eq!(around(2).as_slice(), "d e");
+        assert_eq!(around(3).as_slice(), "c d e");
+        assert_eq!(around(5).as_slice(), "b c d e f");
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::HashSet;
+
+use {
+    Cache,
+    CacheMut,
+    MarkovGenerator,
+    Symbol,
+    DOCUMENT_BOUNDARY,
+    SENTENCE_START,
+};
+
+/// What `prune` or `compact` removed from the cache.
+#[deriving(Clone, PartialEq, Show)]
+pub struct PruneStats {
+    pub states: uint,
+    pub transitions: uint,
+}
+
+impl<C> MarkovGenerator<C> where C: Cache {
+    /// Allow `generate_text` to start only from positions whose state is
+    /// still in the cache.
+    pub fn rebuild_seeds(&mut self) {
+        let mut seeds = Vec::new();
+
+        if self.words.len() >= self.order {
+            for start in range(0, self.words.len() - self.order + 1) {
+                if self.cache.has(self.words[start..start + self.order]) {
+                    seeds.push(start);
+                }
+            }
+        }
+
+        self.seeds = Some(seeds);
+    }
+}
+
+impl<C> MarkovGenerator<C> where C: CacheMut {
+    /// Remove every transition seen less than `min_count` times, then
+    /// `compact` the cache.
+    pub fn prune(&mut self, min_count: u32) -> PruneStats {
+        let (states_before, transitions_before) = self.cache_size();
+
+        self.cache.retain(|_, transition| transition.count >= min_count);
+        match self.backward {
+            Some(ref mut backward) => backward.retain(|_, transition| transition.count >= min_count),
+            None => {}
+        }
+
+        let (states, transitions) = self.cache_size();
+        let compacted = self.compact();
+
+        PruneStats {
+            states: states_before - states + compacted.states,
+            transitions: transitions_before - transitions + compacted.transitions,
+        }
+    }
+
+    /// Remove the states that can no longer be reached from the start of the
+    /// corpus or from sentence or document starts, and rebuild the seeds.
+    pub fn compact(&mut self) -> PruneStats {
+        let (states_before, transitions_before) = self.cache_size();
+
+        let order = self.order;
+        let mut pending: Vec<Vec<Symbol>> = Vec::new();
+        if self.words.len() >= order {
+            pending.push(self.words[..order].to_vec());
+        }
+        for (key, _) in self.cache.states() {
+            if key.len() == order && key.iter().all(|&symbol| symbol == SENTENCE_START || symbol == DOCUMENT_BOUNDARY) {
+                pending.push(key.to_vec());
+            }
+        }
+
+        // Walk the chain from those roots, so that states only reachable
+        // through removed ones are removed too.
+        let mut reachable: HashSet<Vec<Symbol>> = HashSet::new();
+        loop {
+            let key = match pending.pop() {
+                Some(key) => key,
+                None => break,
+            };
+            if order == 0 || !reachable.insert(key.clone()) {
+                continue;
+            }
+
+            match self.cache.get(key.as_slice()) {
+                Some(transitions) => for transition in transitions.iter() {
+                    let mut next = key[1..].to_vec();
+                    next.push(transition.symbol);
+                    if !reachable.contains(&next) {
+                        pending.push(next);
+                    }
+                },
+                None => {}
+            }
+        }
+
+        self.cache.retain(|key, _| key.len() != order || order == 0 || reachable.contains(key));
+
+        let (states, transitions) = self.cache_size();
+        self.rebuild_seeds();
+
+        PruneStats {
+            states: states_before - states,
+            transitions: transitions_before - transitions,
+        }
+    }
+
+    fn cache_size(&self) -> (uint, uint) {
+        self.cache.states().fold((0, 0), |(states, transitions), (_, successors)| {
+            (states + 1, transitions + successors.len())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::PruneStats;
+    use testing::Model;
+    use {
+        Cache,
+        CacheMut,
+        MarkovGenerator,
+    };
+
+    #[test]
+    fn compact_removes_chains_only_reachable_through_removed_states() {
+        let mut markov: Model = MarkovGenerator::with_order(HashMap::new(), 1);
+        markov.feed_from_words(&["a", "b", "c"]);
+        let (x, y, z) = (markov.symbols.intern("x"), markov.symbols.intern("y"), markov.symbols.intern("z"));
+        markov.cache.add(&[x], y, 1);
+        markov.cache.add(&[y], z, 1);
+
+        assert_eq!(markov.compact(), PruneStats { states: 2, transitions: 2 });
+        assert!(!markov.cache.has(&[x]));
+        assert!(!markov.cache.has(&[y]));
+        assert!(markov.cache.has(&[markov.symbols.get("b").unwrap()]));
+    }
+}
@@ -0,0 +1,352 @@
+/*!
+ * Readers extracting the text of structured records, each record being fed
+ * as its own document.
+ *
+ * Chat logs keep the text of the messages only, whatever their timestamps:
+ *
+ * ```text
+ * 12:34 <nick> message                          irssi, and most IRC clients
+ * [2014-11-02 12:34:56] <nick> message          bracketed timestamps
+ * 2014-11-02 12:34:56<TAB>nick<TAB>message      WeeChat
+ * [12:34] @user:matrix.org: message             Matrix text exports
+ * ```
+ *
+ * Events such as joins, parts, topic changes or `* nick` actions are skipped.
+ */
+
+use std::io::IoErrorKind;
+use std::mem;
+use serialize::json;
+
+use {
+    CacheMut,
+    FeedError,
+    FeedErrorKind,
+    MarkovGenerator,
+};
+
+/// Where the text of each record is found.
+#[deriving(Clone, PartialEq, Show)]
+pub enum RecordFormat {
+    /// The named column of a CSV file whose first row is a header. Quoted
+    /// fields may contain commas, doubled quotes and line breaks.
+    Csv(String),
+    /// The string at a dotted field path, e.g. `content.body`, in each line
+    /// of a JSON Lines file. Records without it are skipped.
+    JsonLines(String),
+    /// IRC or Matrix log lines.
+    ChatLog,
+}
+
+impl<C> MarkovGenerator<C> where C: CacheMut {
+    /// Feed the text of every record of `reader` as its own document.
+    pub fn feed_records<B: Buffer>(&mut self, reader: &mut B, format: &RecordFormat) -> Result<(), FeedError> {
+        read_records(reader, format, |text| self.feed_document_from_str(text)).map(|_| ())
+    }
+}
+
+/// Where a reader stands, to locate errors.
+#[deriving(Default)]
+struct Position {
+    /// Number of lines read so far.
+    line: uint,
+    /// Byte offset of the start of the last line read.
+    start: u64,
+    /// Byte offset of the next line.
+    next: u64,
+}
+
+/// Call `each` with the text of every record of `reader`, returning the
+/// number of lines read.
+pub fn read_records<B: Buffer>(reader: &mut B, format: &RecordFormat, each: |&str|) -> Result<uint, FeedError> {
+    let mut position: Position = Default::default();
+
+    match *format {
+        RecordFormat::Csv(ref name) => {
+            let column = match try!(read_csv_record(reader, &mut position)) {
+                Some(header) => match header.iter().position(|field| field.as_slice().trim() == name.as_slice()) {
+                    Some(column) => column,
+                    None => {
+                        let message = format!("no `{}` column in the header", name);
+                        return Err(malformed(message, position.line, position.start));
+                    }
+                },
+                None => return Ok(0),
+            };
+
+            loop {
+                match try!(read_csv_record(reader, &mut position)) {
+                    Some(fields) => match fields.get(column) {
+                        Some(text) => each(text.as_slice()),
+                        None => debug!("Skipped short record ending at line {}", position.line),
+                    },
+                    None => break,
+                }
+            }
+        }
+        RecordFormat::JsonLines(ref path) => {
+            loop {
+                let line = match try!(read_line(reader, &mut position)) {
+                    Some(line) => line,
+                    None => break,
+                };
+                if line.as_slice().trim().is_empty() {
+                    continue;
+                }
+
+                let value = match json::from_str(line.as_slice()) {
+                    Ok(value) => value,
+                    Err(err) => return Err(malformed(format!("invalid JSON: {}", err), position.line, position.start)),
+                };
+                let field = path.as_slice().split('.').fold(Some(&value), |value, key| {
+                    value.and_then(|value| value.find(key))
+                });
+                match field.and_then(|field| field.as_string()) {
+                    Some(text) => each(text),
+                    None => debug!("Skipped record without `{}` at line {}", path, position.line),
+                }
+            }
+        }
+        RecordFormat::ChatLog => {
+            loop {
+                let line = match try!(read_line(reader, &mut position)) {
+                    Some(line) => line,
+                    None => break,
+                };
+                match chat_message(line.as_slice()) {
+                    Some(text) => each(text),
+                    None => {}
+                }
+            }
+        }
+    }
+
+    Ok(position.line)
+}
+
+fn malformed(message: String, line: uint, offset: u64) -> FeedError {
+    FeedError {
+        kind: FeedErrorKind::Malformed(message),
+        line: line,
+        offset: offset,
+    }
+}
+
+/// Read the next line, without the byte order mark starting the input.
+fn read_line<B: Buffer>(reader: &mut B, position: &mut Position) -> Result<Option<String>, FeedError> {
+    let bytes = match reader.read_until(b'\n') {
+        Ok(bytes) => bytes,
+        Err(ref err) if err.kind == IoErrorKind::EndOfFile => return Ok(None),
+        Err(err) => return Err(FeedError {
+            kind: FeedErrorKind::Io(err),
+            line: position.line + 1,
+            offset: position.next,
+        }),
+    };
+    position.line += 1;
+    position.start = position.next;
+    position.next += bytes.len() as u64;
+
+    match String::from_utf8(bytes) {
+        Ok(ref line) if position.line == 1 && line.as_slice().starts_with("\ufeff") => {
+            Ok(Some(line.as_slice().slice_from("\ufeff".len()).to_string()))
+        }
+        Ok(line) => Ok(Some(line)),
+        Err(_) => Err(FeedError {
+            kind: FeedErrorKind::InvalidUtf8,
+            line: position.line,
+            offset: position.start,
+        }),
+    }
+}
+
+/// Read the next non-blank CSV record, whose quoted fields may span several
+/// lines.
+fn read_csv_record<B: Buffer>(reader: &mut B, position: &mut Position) -> Result<Option<Vec<String>>, FeedError> {
+    let (mut first, mut offset) = (position.line + 1, position.next);
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+
+    loop {
+        let line = match try!(read_line(reader, position)) {
+            Some(line) => line,
+            None if quoted => return Err(malformed("unterminated quoted field".to_string(), first, offset)),
+            None => return Ok(None),
+        };
+
+        let mut chars = line.as_slice().chars().peekable();
+        loop {
+            match chars.next() {
+                Some('"') if quoted => {
+                    if chars.peek() == Some(&'"') {
+                        chars.next();
+                        field.push('"');
+                    } else {
+                        quoted = false;
+                    }
+                }
+                Some('"') if field.is_empty() => quoted = true,
+                Some(',') if !quoted => fields.push(mem::replace(&mut field, String::new())),
+                Some('\r') | Some('\n') if !quoted => {}
+                Some(c) => field.push(c),
+                None => break,
+            }
+        }
+
+        if quoted {
+            continue;
+        }
+        if fields.is_empty() && field.is_empty() {
+            first = position.line + 1;
+            offset = position.next;
+            continue;
+        }
+
+        fields.push(field);
+        return Ok(Some(fields));
+    }
+}
+
+/// Text of the message of a chat log line, `None` for other events.
+fn chat_message(line: &str) -> Option<&str> {
+    let line = line.trim_right_chars(['\r', '\n'].as_slice());
+
+    // WeeChat separates the time, the nick and the message with tabs, with
+    // `-->`, `<--` or `--` in place of the nick for events.
+    match line.find('\t') {
+        Some(first) => {
+            let rest = line[first + 1..];
+            return match rest.find('\t') {
+                Some(second) => match rest[..second].trim() {
+                    "" | "-->" | "<--" | "--" | "*" | "=!=" => None,
+                    _ => non_empty(rest[second + 1..]),
+                },
+                None => None,
+            };
+        }
+        None => {}
+    }
+
+    let line = skip_timestamp(line.trim());
+    if line.starts_with("<") {
+        line.find('>').and_then(|end| non_empty(line[end + 1..]))
+    } else if line.starts_with("@") {
+        // Matrix user IDs contain a colon, but no space.
+        line.find_str(": ").and_then(|end| non_empty(line[end + 2..]))
+    } else {
+        None
+    }
+}
+
+/// Remove the leading timestamp of a log line, bracketed or not.
+fn skip_timestamp(line: &str) -> &str {
+    if line.starts_with("[") {
+        return match line.find(']') {
+            Some(end) => line[end + 1..].trim_left(),
+            None => line,
+        };
+    }
+
+    let mut line = line;
+    loop {
+        let word = match line.find(' ') {
+            Some(end) => line[..end],
+            None => return line,
+        };
+        let timestamp = word.chars().any(|c| c.is_digit()) &&
+                        word.chars().all(|c| c.is_digit() || "-:./+TZ".contains_char(c));
+        if !timestamp {
+            return line;
+        }
+        line = line[word.len()..].trim_left();
+    }
+}
+
+fn non_empty(text: &str) -> Option<&str> {
+    let text = text.trim();
+    if text.is_empty() { None } else { Some(text) }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::default::Default;
+    use std::io::BufReader;
+
+    use super::{
+        chat_message,
+        read_csv_record,
+        read_records,
+        skip_timestamp,
+        Position,
+        RecordFormat,
+    };
+    use FeedErrorKind;
+
+    fn records(input: &[u8], format: RecordFormat) -> Vec<String> {
+        let mut texts = Vec::new();
+        read_records(&mut BufReader::new(input), &format, |text| texts.push(text.to_string())).unwrap();
+        texts
+    }
+
+    #[test]
+    fn csv_fields() {
+        let mut reader = BufReader::new(b"a,\"b,c\",\"say \"\"hi\"\"\"\r\n\n\"multi\nline\",x\n");
+        let mut position: Position = Default::default();
+
+        let first = read_csv_record(&mut reader, &mut position).unwrap().unwrap();
+        assert_eq!(first, vec!["a".to_string(), "b,c".to_string(), "say \"hi\"".to_string()]);
+        let second = read_csv_record(&mut reader, &mut position).unwrap().unwrap();
+        assert_eq!(second, vec!["multi\nline".to_string(), "x".to_string()]);
+        assert!(read_csv_record(&mut reader, &mut position).unwrap().is_none());
+        assert_eq!(position.line, 4);
+    }
+
+    #[test]
+    fn csv_unterminated_quote() {
+        let mut reader = BufReader::new(b"ok\n\"open\nstill open\n");
+        let mut position: Position = Default::default();
+
+        read_csv_record(&mut reader, &mut position).unwrap();
+        let err = read_csv_record(&mut reader, &mut position).unwrap_err();
+        assert_eq!((err.line, err.offset), (2, 3));
+    }
+
+    #[test]
+    fn csv_column_after_byte_order_mark() {
+        let texts = records(b"\xef\xbb\xbftext,id\nhello,1\nworld,2\n", RecordFormat::Csv("text".to_string()));
+        assert_eq!(texts, vec!["hello".to_string(), "world".to_string()]);
+    }
+
+    #[test]
+    fn json_lines_errors_locate_the_line() {
+        let format = RecordFormat::JsonLines("body".to_string());
+        let err = read_records(&mut BufReader::new(b"{\"body\": \"a\"}\nnot json\n"), &format, |_| {}).unwrap_err();
+        match err.kind {
+            FeedErrorKind::Malformed(..) => {}
+            _ => panic!("unexpected error {}", err),
+        }
+        assert_eq!((err.line, err.offset), (2, 14));
+    }
+
+    #[test]
+    fn chat_messages() {
+        assert_eq!(chat_message("12:34 <nick> hello there\n"), Some("hello there"));
+        assert_eq!(chat_message("[2014-11-02 12:34:56] <nick> hi"), Some("hi"));
+        assert_eq!(chat_message("2014-11-02 12:34:56\tnick\tweechat message"), Some("weechat message"));
+        assert_eq!(chat_message("2014-11-02 12:34:56\t-->\tnick has joined"), None);
+        assert_eq!(chat_message("[12:34] @user:matrix.org: from matrix"), Some("from matrix"));
+        assert_eq!(chat_message("12:34 * nick waves"), None);
+        assert_eq!(chat_message("12:34 <nick>   "), None);
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(skip_timestamp("[12:34] rest"), "rest");
+        assert_eq!(skip_timestamp("2014-11-02 12:34:56 rest"), "rest");
+        assert_eq!(skip_timestamp("2014-11-02T12:34:56Z rest"), "rest");
+        assert_eq!(skip_timestamp("-- rest"), "-- rest");
+        assert_eq!(skip_timestamp("12:34"), "12:34");
+        assert_eq!(skip_timestamp("<nick> 12:34"), "<nick> 12:34");
+    }
+}
@@ -0,0 +1,184 @@
+use std::cmp;
+use std::default::Default;
+use std::num::Float;
+use std::rand::Rng;
+
+use {
+    choose_weighted,
+    Transition,
+};
+
+/// How successors are picked during generation.
+///
+/// The default samples proportionally to the transition counts.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Sampling {
+    /// Counts are raised to the power `1 / temperature` before sampling:
+    /// below 1 favours frequent successors, above 1 flattens the distribution.
+    pub temperature: f64,
+    /// Only keep the `k` most frequent successors.
+    pub top_k: Option<uint>,
+    /// Only keep the most frequent successors whose probabilities add up to
+    /// at least `p` (nucleus sampling).
+    pub top_p: Option<f64>,
+    /// Always pick the most frequent successor.
+    pub greedy: bool,
+}
+
+impl Default for Sampling {
+    fn default() -> Sampling {
+        Sampling {
+            temperature: 1.0,
+            top_k: None,
+            top_p: None,
+            greedy: false,
+        }
+    }
+}
+
+impl Sampling {
+    pub fn greedy() -> Sampling {
+        Sampling {
+            greedy: true,
+            ..Default::default()
+        }
+    }
+
+    fn is_plain(&self) -> bool {
+        self.temperature == 1.0 && self.top_k.is_none() && self.top_p.is_none() && !self.greedy
+    }
+
+    /// Pick one of `transitions` according to these options.
+    pub fn choose<'a, R: Rng>(&self, rng: &mut R, transitions: &'a [Transition]) -> Option<&'a Transition> {
+        if self.is_plain() {
+            return choose_weighted(rng, transitions);
+        }
+
+        // Most frequent first, ties keeping their original order.
+        let mut ranked: Vec<&Transition> = transitions.iter().filter(|transition| transition.count > 0).collect();
+        ranked.sort_by(|a, b| b.count.cmp(&a.count));
+        if ranked.is_empty() {
+            return None;
+        }
+
+        if self.greedy || self.temperature <= 0.0 {
+            return Some(ranked[0]);
+        }
+
+        match self.top_k {
+            Some(k) => ranked.truncate(cmp::max(k, 1)),
+            None => {}
+        }
+
+        let max = ranked[0].count as f64;
+        let exponent = 1.0 / self.temperature;
+        let weights: Vec<f64> = ranked.iter()
+                                      .map(|transition| (transition.count as f64 / max).powf(exponent))
+                                      .collect();
+        let mut total = weights.iter().fold(0.0, |total, &weight| total + weight);
+
+        match self.top_p {
+            Some(p) => {
+                let mut kept = 0.0;
+                let mut len = 0;
+                for &weight in weights.iter() {
+                    kept += weight;
+                    len += 1;
+                    if kept >= p * total {
+                        break;
+                    }
+                }
+                ranked.truncate(len);
+                total = kept;
+            }
+            None => {}
+        }
+
+        let mut target = rng.gen::<f64>() * total;
+        for (transition, &weight) in ranked.iter().zip(weights.iter()) {
+            if target < weight {
+                return Some(*transition);
+            }
+            target -= weight;
+        }
+
+        // Rounding errors may leave a tiny bit of the target.
+        ranked.last().map(|transition| *transition)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::default::Default;
+
+    use super::Sampling;
+    use {
+        seeded_rng,
+        Transition,
+    };
+
+    /// Successors 0 to 4 seen 5, 3, 1, 1 and 0 times.
+    fn transitions() -> Vec<Transition> {
+        [5, 3, 1, 1, 0].iter().enumerate().map(|(symbol, &count)| Transition {
+            symbol: symbol as u32,
+            count: count,
+        }).collect()
+    }
+
+    /// How many times each successor is picked in 1000 draws.
+    fn draws(sampling: &Sampling) -> Vec<uint> {
+        let transitions = transitions();
+        let mut rng = seeded_rng(11);
+        let mut picked = Vec::from_elem(transitions.len(), 0u);
+        for _ in range(0u, 1000) {
+            let transition = sampling.choose(&mut rng, transitions.as_slice()).unwrap();
+            picked.as_mut_slice()[transition.symbol as uint] += 1;
+        }
+        picked
+    }
+
+    /// The successors picked at least once.
+    fn kept(sampling: &Sampling) -> Vec<uint> {
+        draws(sampling).iter().enumerate().filter(|&(_, &count)| count > 0).map(|(symbol, _)| symbol).collect()
+    }
+
+    #[test]
+    fn greedy_picks_the_most_frequent() {
+        assert_eq!(kept(&Sampling::greedy()), vec![0]);
+        assert_eq!(kept(&Sampling { temperature: 0.0, ..Default::default() }), vec![0]);
+    }
+
+    #[test]
+    fn top_k_keeps_the_k_most_frequent() {
+        assert_eq!(kept(&Sampling { top_k: Some(2), ..Default::default() }), vec![0, 1]);
+        assert_eq!(kept(&Sampling { top_k: Some(0), ..Default::default() }), vec![0]);
+        assert_eq!(kept(&Sampling { top_k: Some(10), ..Default::default() }), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn top_p_keeps_the_smallest_set_reaching_p() {
+        // The probabilities are 0.5, 0.3, 0.1 and 0.1.
+        assert_eq!(kept(&Sampling { top_p: Some(0.5), ..Default::default() }), vec![0]);
+        assert_eq!(kept(&Sampling { top_p: Some(0.7), ..Default::default() }), vec![0, 1]);
+        assert_eq!(kept(&Sampling { top_p: Some(1.0), ..Default::default() }), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn temperature_sharpens_or_flattens() {
+        let plain = draws(&Default::default());
+        let cold = draws(&Sampling { temperature: 0.5, ..Default::default() });
+        let hot = draws(&Sampling { temperature: 2.0, ..Default::default() });
+
+        assert!(cold[0] > plain[0] && plain[0] > hot[0]);
+        assert!(cold[3] < plain[3] && plain[3] < hot[3]);
+        assert_eq!(plain[4] + cold[4] + hot[4], 0);
+    }
+
+    #[test]
+    fn nothing_to_pick() {
+        let mut rng = seeded_rng(11);
+        let unseen = [Transition { symbol: 0, count: 0 }];
+        assert!(Sampling::greedy().choose(&mut rng, &unseen).is_none());
+        assert!(Sampling { top_k: Some(1), ..Default::default() }.choose(&mut rng, &[]).is_none());
+    }
+}
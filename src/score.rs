@@ -0,0 +1,247 @@
+use std::cmp;
+use std::num::Float;
+
+use {
+    Cache,
+    MarkovGenerator,
+    Symbol,
+    SENTENCE_END,
+    SENTENCE_START,
+};
+
+/// How probabilities are smoothed so that unseen transitions keep some mass.
+///
+/// The vocabulary has one extra slot for unknown words.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Smoothing {
+    /// Add `k` to the count of every possible successor. Unknown or short
+    /// histories get the uniform estimate `1 / V`.
+    AddK(f64),
+    /// Mix the state estimate with the unigram one: `lambda * P(w | h) +
+    /// (1 - lambda) * P(w)`, `P(w)` being the add-one smoothed unigram
+    /// distribution of the fed words, used alone for unknown histories.
+    Interpolated(f64),
+    /// Interpolated Kneser-Ney with the given absolute discount, falling
+    /// back on the add-one smoothed continuation distribution.
+    KneserNey(f64),
+}
+
+impl Smoothing {
+    /// Whether the parameter is usable: `k` must be positive, `lambda` and
+    /// the discount between 0 and 1.
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            Smoothing::AddK(k) => k > 0.0,
+            Smoothing::Interpolated(lambda) => lambda >= 0.0 && lambda <= 1.0,
+            Smoothing::KneserNey(discount) => discount >= 0.0 && discount <= 1.0,
+        }
+    }
+}
+
+/// Log-probability of a token sequence under a model.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Score {
+    /// Natural logarithm of the probability of the sequence.
+    pub log_prob: f64,
+    /// Number of scored tokens.
+    pub tokens: uint,
+    /// `exp(-log_prob / tokens)`, 1 for an empty sequence.
+    pub perplexity: f64,
+}
+
+/// Scores token sequences, computing the model statistics only once.
+pub struct Scorer<'a, C: 'a> {
+    markov: &'a MarkovGenerator<C>,
+    smoothing: Smoothing,
+    /// Number of occurrences of each symbol in the fed words.
+    unigrams: Vec<u64>,
+    /// Number of distinct states each symbol follows.
+    continuations: Vec<u64>,
+    /// Number of distinct (state, successor) pairs.
+    transitions: u64,
+}
+
+impl<C> MarkovGenerator<C> where C: Cache {
+    /// Create a scorer for this model. Panics if `smoothing` is not valid,
+    /// see `Smoothing::is_valid`.
+    pub fn scorer(&self, smoothing: Smoothing) -> Scorer<C> {
+        assert!(smoothing.is_valid(), "invalid smoothing {}", smoothing);
+
+        let mut unigrams = Vec::from_elem(self.symbols.len(), 0u64);
+        for &symbol in self.words.iter() {
+            unigrams[symbol as uint] += 1;
+        }
+
+        let mut continuations = Vec::from_elem(self.symbols.len(), 0u64);
+        let mut transitions = 0;
+        for (key, successors) in self.cache.states() {
+            if key.len() != self.order {
+                continue;
+            }
+            for transition in successors.iter() {
+                continuations[transition.symbol as uint] += 1;
+                transitions += 1;
+            }
+        }
+
+        Scorer {
+            markov: self,
+            smoothing: smoothing,
+            unigrams: unigrams,
+            continuations: continuations,
+            transitions: transitions,
+        }
+    }
+
+    /// Score `tokens` with a scorer built for this call only.
+    pub fn score(&self, tokens: &[&str], smoothing: Smoothing) -> Score {
+        self.scorer(smoothing).score(tokens)
+    }
+
+    /// Tokenize `text` with the generator's tokenizer and score the tokens.
+    pub fn score_str(&self, text: &str, smoothing: Smoothing) -> Score {
+        let tokens = self.tokenizer.tokenize(text);
+        self.score(tokens.as_slice(), smoothing)
+    }
+}
+
+impl<'a, C> Scorer<'a, C> where C: Cache {
+    /// Score every token of `tokens` given the ones before it. Models fed
+    /// with sentence markers also score the end of the sentence.
+    pub fn score(&self, tokens: &[&str]) -> Score {
+        let markov = self.markov;
+        let padding = if markov.sentences { cmp::max(markov.order, 1) } else { 0 };
+
+        let mut symbols: Vec<Option<Symbol>> = Vec::from_elem(padding, Some(SENTENCE_START));
+        symbols.extend(tokens.iter().map(|&token| markov.symbols.get(token)));
+        if markov.sentences {
+            symbols.push(Some(SENTENCE_END));
+        }
+
+        let mut log_prob = 0.0;
+        for index in range(padding, symbols.len()) {
+            let history = symbols[index - cmp::min(index, markov.order)..index];
+            log_prob += self.probability(history, symbols[index]).ln();
+        }
+
+        let scored = symbols.len() - padding;
+        Score {
+            log_prob: log_prob,
+            tokens: scored,
+            perplexity: if scored == 0 { 1.0 } else { (-log_prob / scored as f64).exp() },
+        }
+    }
+
+    /// Smoothed probability of `word` following `history`, `None` standing
+    /// for words the model never saw.
+    pub fn probability(&self, history: &[Option<Symbol>], word: Option<Symbol>) -> f64 {
+        let vocabulary = self.unigrams.len() as f64 + 1.0;
+
+        // Counts of the state and of the word following it.
+        let (total, count, distinct) = self.state_counts(history, word);
+
+        let unigram = {
+            let count = word.map_or(0, |symbol| self.unigrams[symbol as uint]) as f64;
+            (count + 1.0) / (self.markov.words.len() as f64 + vocabulary)
+        };
+
+        match self.smoothing {
+            Smoothing::AddK(k) => (count + k) / (total + k * vocabulary),
+            Smoothing::Interpolated(lambda) => {
+                if total > 0.0 {
+                    lambda * count / total + (1.0 - lambda) * unigram
+                } else {
+                    unigram
+                }
+            }
+            Smoothing::KneserNey(discount) => {
+                let continuation = {
+                    let count = word.map_or(0, |symbol| self.continuations[symbol as uint]) as f64;
+                    (count + 1.0) / (self.transitions as f64 + vocabulary)
+                };
+
+                if total > 0.0 {
+                    (count - discount).max(0.0) / total + discount * distinct / total * continuation
+                } else {
+                    continuation
+                }
+            }
+        }
+    }
+
+    /// Total count of the state made of `history`, count of `word` in it and
+    /// number of distinct successors, all zero if the state is unknown.
+    fn state_counts(&self, history: &[Option<Symbol>], word: Option<Symbol>) -> (f64, f64, f64) {
+        if history.len() != self.markov.order {
+            return (0.0, 0.0, 0.0);
+        }
+
+        let mut key = Vec::with_capacity(history.len());
+        for symbol in history.iter() {
+            match *symbol {
+                Some(symbol) => key.push(symbol),
+                None => return (0.0, 0.0, 0.0),
+            }
+        }
+
+        match self.markov.cache.get(key.as_slice()) {
+            Some(successors) => {
+                let total = successors.iter().fold(0u64, |total, transition| total + transition.count as u64);
+                let count = successors.iter()
+                                      .find(|transition| Some(transition.symbol) == word)
+                                      .map_or(0, |transition| transition.count);
+                (total as f64, count as f64, successors.len() as f64)
+            }
+            None => (0.0, 0.0, 0.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::Float;
+
+    use super::{
+        Scorer,
+        Smoothing,
+    };
+    use testing::fixture;
+    use {
+        Cache,
+        Symbol,
+    };
+
+    /// Sum of the probabilities of every known word and of an unknown one
+    /// after `history`.
+    fn total<C: Cache>(scorer: &Scorer<C>, history: &[Option<Symbol>]) -> f64 {
+        let known = range(0, scorer.markov.symbols.len()).fold(0.0, |total, symbol| {
+            total + scorer.probability(history, Some(symbol as Symbol))
+        });
+        known + scorer.probability(history, None)
+    }
+
+    #[test]
+    fn probabilities_sum_to_one() {
+        let markov = fixture();
+        let the = markov.symbols.get("the");
+        let cat = markov.symbols.get("cat");
+        let mat = markov.symbols.get("mat");
+        // Known, unknown, short and partly unknown histories.
+        let histories = vec![vec![the, cat], vec![mat, mat], vec![the], vec![None, cat]];
+
+        for smoothing in [Smoothing::AddK(1.0), Smoothing::AddK(0.01), Smoothing::Interpolated(0.8),
+                          Smoothing::KneserNey(0.75)].iter() {
+            let scorer = markov.scorer(smoothing.clone());
+            for history in histories.iter() {
+                let total = total(&scorer, history.as_slice());
+                assert!((total - 1.0).abs() < 1e-9, "{} after {}: {}", smoothing, history, total);
+            }
+        }
+    }
+
+    #[test]
+    #[should_fail]
+    fn add_zero_is_rejected() {
+        fixture().scorer(Smoothing::AddK(0.0));
+    }
+}
@@ -0,0 +1,45 @@
+/*!
+ * Helpers shared by the unit tests.
+ */
+
+use std::collections::HashMap;
+
+use {
+    Cache,
+    MarkovGenerator,
+    Symbol,
+    Transition,
+};
+
+pub type Model = MarkovGenerator<HashMap<Vec<Symbol>, Vec<Transition>>>;
+
+/// Every state of `markov` along with its successors, sorted by key.
+pub fn states<C: Cache>(markov: &MarkovGenerator<C>) -> Vec<(Vec<Symbol>, Vec<Transition>)> {
+    let mut states: Vec<(Vec<Symbol>, Vec<Transition>)> = markov.cache.states().map(|(key, transitions)| {
+        (key.to_vec(), transitions.to_vec())
+    }).collect();
+    states.sort_by(|&(ref a, _), &(ref b, _)| a.cmp(b));
+    states
+}
+
+/// Two documents of sentences, stored with every order up to 2 and seeds.
+pub fn fixture() -> Model {
+    let mut markov = MarkovGenerator::with_backoff(HashMap::new(), 2);
+    markov.sentences = true;
+    markov.feed_document(&["the", "cat", "sat", "on", "the", "mat"]);
+    markov.feed_document(&["the", "dog", "sat"]);
+    markov.rebuild_seeds();
+    markov
+}
+
+/// Check that `a` and `b` hold the same model.
+pub fn assert_same<C: Cache, D: Cache>(a: &MarkovGenerator<C>, b: &MarkovGenerator<D>) {
+    assert_eq!(a.order, b.order);
+    assert_eq!(a.min_order, b.min_order);
+    assert_eq!(a.tokenizer.name(), b.tokenizer.name());
+    assert_eq!(a.sentences, b.sentences);
+    assert_eq!(a.symbols.names(), b.symbols.names());
+    assert_eq!(a.words, b.words);
+    assert_eq!(a.seeds, b.seeds);
+    assert_eq!(states(a), states(b));
+}
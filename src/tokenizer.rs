@@ -0,0 +1,162 @@
+use detokenizer::{
+    Concat,
+    Detokenizer,
+    Prose,
+    Spaced,
+};
+
+/// Splits text into the tokens the chain is trained on.
+pub trait Tokenizer {
+    /// Name recorded in saved models, see `tokenizer_by_name`.
+    fn name(&self) -> &'static str;
+    fn tokenize<'a>(&self, text: &'a str) -> Vec<&'a str>;
+
+    /// The detokenizer that joins generated tokens back into text.
+    fn detokenizer(&self) -> Box<Detokenizer + 'static> {
+        box Spaced
+    }
+}
+
+/// Words separated by spaces, tabs and line breaks, punctuation included.
+#[deriving(Clone, Show)]
+pub struct Whitespace;
+
+/// Words made of alphanumeric characters (and inner apostrophes), every
+/// other non-space character being a token of its own.
+#[deriving(Clone, Show)]
+pub struct WordPunct;
+
+/// One token per character, spaces included.
+#[deriving(Clone, Show)]
+pub struct Characters;
+
+/// One token per extended grapheme cluster, spaces included.
+#[deriving(Clone, Show)]
+pub struct Graphemes;
+
+impl Tokenizer for Whitespace {
+    fn name(&self) -> &'static str {
+        "whitespace"
+    }
+
+    fn tokenize<'a>(&self, text: &'a str) -> Vec<&'a str> {
+        let seps = |c: char| [' ', '\t', '\n', '\r'].contains(&c);
+        let filter = |word: &str| !word.is_empty();
+
+        text.split(seps).filter(|word| filter(*word)).collect()
+    }
+}
+
+impl Tokenizer for WordPunct {
+    fn name(&self) -> &'static str {
+        "word-punct"
+    }
+
+    fn detokenizer(&self) -> Box<Detokenizer + 'static> {
+        box Prose
+    }
+
+    fn tokenize<'a>(&self, text: &'a str) -> Vec<&'a str> {
+        let mut tokens = Vec::new();
+        let mut word_start = None;
+
+        for (index, c) in text.char_indices() {
+            let in_word = c.is_alphanumeric() || (c == '\'' && word_start.is_some() && {
+                let next = text[index + 1..].chars().next();
+                next.map_or(false, |next| next.is_alphanumeric())
+            });
+
+            if in_word {
+                if word_start.is_none() {
+                    word_start = Some(index);
+                }
+                continue;
+            }
+
+            match word_start.take() {
+                Some(start) => tokens.push(text[start..index]),
+                None => {}
+            }
+            if !c.is_whitespace() {
+                tokens.push(text[index..index + c.len_utf8()]);
+            }
+        }
+
+        match word_start {
+            Some(start) => tokens.push(text[start..]),
+            None => {}
+        }
+
+        tokens
+    }
+}
+
+impl Tokenizer for Characters {
+    fn name(&self) -> &'static str {
+        "characters"
+    }
+
+    fn detokenizer(&self) -> Box<Detokenizer + 'static> {
+        box Concat
+    }
+
+    fn tokenize<'a>(&self, text: &'a str) -> Vec<&'a str> {
+        text.char_indices().map(|(index, c)| text[index..index + c.len_utf8()]).collect()
+    }
+}
+
+impl Tokenizer for Graphemes {
+    fn name(&self) -> &'static str {
+        "graphemes"
+    }
+
+    fn detokenizer(&self) -> Box<Detokenizer + 'static> {
+        box Concat
+    }
+
+    fn tokenize<'a>(&self, text: &'a str) -> Vec<&'a str> {
+        text.graphemes(true).collect()
+    }
+}
+
+/// Return the built-in tokenizer with the given name.
+pub fn tokenizer_by_name(name: &str) -> Option<Box<Tokenizer + 'static>> {
+    match name {
+        "whitespace" => Some(box Whitespace as Box<Tokenizer + 'static>),
+        "word-punct" => Some(box WordPunct as Box<Tokenizer + 'static>),
+        "characters" => Some(box Characters as Box<Tokenizer + 'static>),
+        "graphemes" => Some(box Graphemes as Box<Tokenizer + 'static>),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Tokenizer,
+        WordPunct,
+    };
+
+    #[test]
+    fn word_punct_splits_punctuation() {
+        assert_eq!(WordPunct.tokenize("Don't stop, it's fine!"),
+                   vec!["Don't", "stop", ",", "it's", "fine", "!"]);
+        assert_eq!(WordPunct.tokenize("end..."), vec!["end", ".", ".", "."]);
+        assert_eq!(WordPunct.tokenize("café 42€"), vec!["café", "42", "€"]);
+        assert_eq!(WordPunct.tokenize("  \t\n"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn word_punct_apostrophes() {
+        // Only apostrophes between letters belong to the word.
+        assert_eq!(WordPunct.tokenize("rock 'n' roll"), vec!["rock", "'", "n", "'", "roll"]);
+        assert_eq!(WordPunct.tokenize("dogs' toys"), vec!["dogs", "'", "toys"]);
+    }
+
+    #[test]
+    fn word_punct_round_trips_through_prose() {
+        let text = "Hello, world. Is it (really) here?";
+        let tokens = WordPunct.tokenize(text);
+        assert_eq!(WordPunct.detokenizer().detokenize(tokens.as_slice()), text.to_string());
+    }
+}